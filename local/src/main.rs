@@ -2,7 +2,7 @@ use axum::{
     Router,
     body::Body,
     extract::Multipart,
-    http::Request,
+    http::{HeaderMap, Request},
     response::{Html, IntoResponse, Response},
     routing::{delete, get, post},
 };
@@ -14,6 +14,7 @@ async fn main() {
     let app = Router::new()
         .route("/", get(show_index))
         .route("/post", post(upload_handler))
+        .route("/taxonomy", get(taxonomy_handler))
         .route("/image", post(image_handler))
         .route("/image", delete(delete_image_handler))
     // .layer(DefaultBodyLimit::max(
@@ -43,7 +44,7 @@ async fn show_index() -> impl IntoResponse {
 }
 
 async fn upload_handler(multipart: Multipart) -> Html<String> {
-    match post_form::handle(multipart).await {
+    match post_form::handle_post(multipart).await {
         Ok(_) => Html(
             r#"<!doctype html>
 <html lang="en">
@@ -76,6 +77,13 @@ async fn upload_handler(multipart: Multipart) -> Html<String> {
     }
 }
 
+async fn taxonomy_handler(headers: HeaderMap) -> Response {
+    match post_form::handle_taxonomy(headers).await {
+        Ok(response) => response,
+        Err(err) => err.into_response(),
+    }
+}
+
 async fn image_handler(req: Request<Body>) -> Response<Body> {
     image_process::handle(req).await
 }
@@ -1,15 +1,60 @@
+use crate::notifier::{Announcement, Delivery, Notifier, Notify};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 
+// Campaigns made by plogtion, to tell them apart from other newsletters
+const TAG: &str = "plog";
+// Campaigns listed per request, Brevo's maximum
+const PAGE_SIZE: usize = 100;
+// A campaign in any other status can still be changed
+const SENT_STATUSES: [&str; 3] = ["sent", "in_process", "archive"];
+
+/// Brevo only takes campaigns scheduled in the future.
+pub const LIVE_DELAY: chrono::Duration = chrono::Duration::minutes(1);
+
+/// Parses recipient list IDs written like `2, 5`.
+pub fn parse_list_ids(text: &str) -> Result<Vec<i64>, String> {
+    let ids = text
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().map_err(|_| format!("Invalid list ID {id}")))
+        .collect::<Result<Vec<_>, String>>()?;
+    if ids.is_empty() {
+        return Err("No recipient lists".to_string());
+    }
+    Ok(ids)
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid {name}: {value}"))
+}
+
+/// What a newsletter about a post says, and who gets it when.
+pub struct Newsletter {
+    pub title: String,
+    pub description: String,
+    pub image_url: String,
+    pub post_url: String,
+    /// The configured lists when not chosen for the post.
+    pub list_ids: Option<Vec<i64>>,
+    pub scheduled_at: DateTime<Utc>,
+}
+
 #[derive(Serialize)]
 struct Sender {
-    pub id: i32,
+    pub id: i64,
 }
 
 #[derive(Serialize)]
 struct Recipients {
     #[serde(rename = "listIds")]
-    pub list_ids: Vec<i32>,
+    pub list_ids: Vec<i64>,
 }
 
 #[derive(Serialize)]
@@ -20,71 +65,508 @@ struct EmailCampaign {
     pub recipients: Recipients,
     pub subject: String,
     #[serde(rename = "templateId")]
-    pub template_id: i32,
+    pub template_id: i64,
     pub params: std::collections::HashMap<String, String>,
     #[serde(rename = "scheduledAt")]
     pub scheduled_at: String,
 }
 
+#[derive(Deserialize)]
+struct CreatedCampaign {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct Campaigns {
+    #[serde(default)]
+    campaigns: Vec<ExistingCampaign>,
+}
+
+#[derive(Deserialize)]
+struct ExistingCampaign {
+    id: i64,
+    status: String,
+    tag: Option<String>,
+}
+
+/// What happened to the newsletter about a post.
+#[derive(Debug, PartialEq)]
+pub enum Campaign {
+    Created(i64),
+    Updated,
+    AlreadySent(i64),
+}
+
 #[derive(Deserialize)]
 struct BrevoResponse {
     code: String,
     message: String,
 }
 
-pub async fn post_campaign(
-    title: String,
-    description: String,
-    image_url: String,
-    post_url: String,
-) -> Result<(), String> {
-    let api_key =
-        std::env::var("BREVO_API_KEY").map_err(|_| "BREVO_API_KEY not set".to_string())?;
-
-    let scheduled_at = chrono::Utc::now() + chrono::Duration::minutes(5);
-    let mut params = std::collections::HashMap::new();
-    params.insert("TITLE".to_string(), title.clone());
-    params.insert("DESCRIPTION".to_string(), description.clone());
-    params.insert("IMAGE_URL".to_string(), image_url.clone());
-    params.insert("POST_URL".to_string(), post_url.clone());
-
-    let campaign = EmailCampaign {
-        tag: "plog".to_string(),
-        name: title.clone(),
-        subject: title.clone(),
-        params,
-        scheduled_at: scheduled_at.to_rfc3339(),
-        sender: Sender { id: 2 },
-        recipients: Recipients { list_ids: vec![2] },
-        template_id: 6,
-    };
-
-    let response = reqwest::Client::new()
-        .post("https://api.brevo.com/v3/emailCampaigns")
-        .header("api-key", api_key)
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .json(&campaign)
-        .send()
-        .await
-        .map_err(|err| format!("Request failed: {err}"))?;
-
-    if response.status().is_success() {
-        info!("Campaign posted successfully!");
-        Ok(())
-    } else {
-        let status = response.status();
-        let error: BrevoResponse = response.json().await.unwrap_or_else(|_| BrevoResponse {
-            code: "Unknown error".to_string(),
-            message: "Failed to parse error response".to_string(),
-        });
-        error!(
-            "Failed to post campaign: {}: {}:{}",
-            status, error.message, error.code
+pub struct Brevo {
+    client: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+    sender_id: i64,
+    list_ids: Vec<i64>,
+    template_id: i64,
+    /// How long to wait when nobody checks that the post is live, enough for a GitHub Pages build.
+    delay: chrono::Duration,
+}
+
+impl Brevo {
+    pub fn new(api_url: String, api_key: Option<String>) -> Self {
+        Brevo {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            api_key,
+            sender_id: 2,
+            list_ids: vec![2],
+            template_id: 6,
+            delay: chrono::Duration::minutes(5),
+        }
+    }
+
+    // BREVO_SENDER_ID, BREVO_LIST_IDS, BREVO_TEMPLATE_ID and BREVO_DELAY (minutes) replace the defaults
+    pub fn from_env() -> Result<Self, String> {
+        let api_url = std::env::var("BREVO_API_URL")
+            .unwrap_or_else(|_| "https://api.brevo.com/v3".to_string());
+        let mut brevo = Brevo::new(api_url, std::env::var("BREVO_API_KEY").ok());
+
+        let number = |name: &str, default: i64| -> Result<i64, String> {
+            match std::env::var(name) {
+                Ok(value) => parse_number(name, &value),
+                Err(_) => Ok(default),
+            }
+        };
+        brevo.sender_id = number("BREVO_SENDER_ID", brevo.sender_id)?;
+        brevo.template_id = number("BREVO_TEMPLATE_ID", brevo.template_id)?;
+        if let Ok(minutes) = std::env::var("BREVO_DELAY") {
+            // A negative delay would schedule the campaign before the post is built
+            let minutes: u32 = parse_number("BREVO_DELAY", &minutes)?;
+            brevo.delay = chrono::Duration::minutes(minutes.into());
+        }
+        if let Ok(list_ids) = std::env::var("BREVO_LIST_IDS") {
+            brevo.list_ids = parse_list_ids(&list_ids)
+                .map_err(|err| format!("Invalid BREVO_LIST_IDS: {err}"))?;
+        }
+        Ok(brevo)
+    }
+
+    /// When the campaign should go out, no earlier than the site can have been built.
+    pub fn scheduled_at(&self, notify: &Notify, live: bool) -> Option<DateTime<Utc>> {
+        let earliest = Utc::now() + if live { LIVE_DELAY } else { self.delay };
+        match notify {
+            Notify::AfterDelay => Some(earliest),
+            Notify::Never => None,
+            Notify::At(time) => Some((*time).max(earliest)),
+        }
+    }
+
+    /// Creates the campaign, or updates the one already made for the post if it has not gone out yet.
+    pub async fn post_campaign(&self, newsletter: &Newsletter) -> Result<Campaign, String> {
+        let api_key = self
+            .api_key
+            .as_ref()
+            .ok_or_else(|| "BREVO_API_KEY not set".to_string())?;
+
+        let mut params = std::collections::HashMap::new();
+        params.insert("TITLE".to_string(), newsletter.title.clone());
+        params.insert("DESCRIPTION".to_string(), newsletter.description.clone());
+        params.insert("IMAGE_URL".to_string(), newsletter.image_url.clone());
+        params.insert("POST_URL".to_string(), newsletter.post_url.clone());
+
+        let campaign = EmailCampaign {
+            tag: campaign_tag(&newsletter.post_url),
+            name: newsletter.title.clone(),
+            subject: newsletter.title.clone(),
+            params,
+            scheduled_at: newsletter.scheduled_at.to_rfc3339(),
+            sender: Sender { id: self.sender_id },
+            recipients: Recipients {
+                list_ids: newsletter
+                    .list_ids
+                    .clone()
+                    .unwrap_or_else(|| self.list_ids.clone()),
+            },
+            template_id: self.template_id,
+        };
+
+        let existing = self
+            .existing_campaign(api_key, &newsletter.post_url)
+            .await?;
+        let request = match &existing {
+            Some(existing) if SENT_STATUSES.contains(&existing.status.as_str()) => {
+                info!(
+                    "Campaign {} about {} is already {}",
+                    existing.id, newsletter.post_url, existing.status
+                );
+                return Ok(Campaign::AlreadySent(existing.id));
+            }
+            Some(existing) => self
+                .client
+                .put(format!("{}/emailCampaigns/{}", self.api_url, existing.id)),
+            None => self.client.post(format!("{}/emailCampaigns", self.api_url)),
+        };
+        let response = request
+            .header("api-key", api_key)
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .json(&campaign)
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+
+        if !response.status().is_success() {
+            return Err(failure("post campaign", response).await);
+        }
+        info!("Campaign scheduled at {}", campaign.scheduled_at);
+        if existing.is_some() {
+            return Ok(Campaign::Updated);
+        }
+        let created: CreatedCampaign = response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse the created campaign: {err}"))?;
+        Ok(Campaign::Created(created.id))
+    }
+
+    // Pages through every campaign, as the list can't be filtered by tag
+    async fn existing_campaign(
+        &self,
+        api_key: &str,
+        post_url: &str,
+    ) -> Result<Option<ExistingCampaign>, String> {
+        let tag = campaign_tag(post_url);
+        for offset in (0..).step_by(PAGE_SIZE) {
+            let response = self
+                .client
+                .get(format!("{}/emailCampaigns", self.api_url))
+                .query(&[
+                    ("type", "classic"),
+                    ("limit", &PAGE_SIZE.to_string()),
+                    ("offset", &offset.to_string()),
+                    ("sort", "desc"),
+                    ("excludeHtmlContent", "true"),
+                ])
+                .header("api-key", api_key)
+                .header("Accept", "application/json")
+                .send()
+                .await
+                .map_err(|err| format!("Request failed: {err}"))?;
+
+            if !response.status().is_success() {
+                return Err(failure("list campaigns", response).await);
+            }
+            let campaigns: Campaigns = response
+                .json()
+                .await
+                .map_err(|err| format!("Failed to parse campaigns: {err}"))?;
+
+            let last_page = campaigns.campaigns.len() < PAGE_SIZE;
+            if let Some(existing) = campaigns
+                .campaigns
+                .into_iter()
+                .find(|campaign| campaign.tag.as_deref() == Some(tag.as_str()))
+            {
+                return Ok(Some(existing));
+            }
+            if last_page {
+                break;
+            }
+        }
+        Ok(None)
+    }
+}
+
+// The list of campaigns leaves out params, so the post is told apart by its tag
+fn campaign_tag(post_url: &str) -> String {
+    format!("{TAG}:{post_url}")
+}
+
+#[async_trait]
+impl Notifier for Brevo {
+    fn name(&self) -> &'static str {
+        "Newsletter"
+    }
+
+    async fn notify(&self, announcement: &Announcement) -> Result<Delivery, String> {
+        let Some(scheduled_at) = self.scheduled_at(&announcement.when, announcement.live) else {
+            return Ok(Delivery::Skipped("not requested".to_string()));
+        };
+        let newsletter = Newsletter {
+            title: announcement.title.clone(),
+            description: announcement.description.clone(),
+            image_url: announcement.image_url.clone(),
+            post_url: announcement.post_url.clone(),
+            list_ids: announcement.list_ids.clone(),
+            scheduled_at,
+        };
+        match self.post_campaign(&newsletter).await? {
+            Campaign::Created(_) | Campaign::Updated => Ok(Delivery::Sent),
+            Campaign::AlreadySent(id) => {
+                Ok(Delivery::Skipped(format!("campaign {id} already went out")))
+            }
+        }
+    }
+}
+
+async fn failure(action: &str, response: reqwest::Response) -> String {
+    let status = response.status();
+    let error: BrevoResponse = response.json().await.unwrap_or_else(|_| BrevoResponse {
+        code: "Unknown error".to_string(),
+        message: "Failed to parse error response".to_string(),
+    });
+    error!(
+        "Failed to {action}: {}: {}:{}",
+        status, error.message, error.code
+    );
+    format!("Failed to {action}: {}: {}", status, error.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use axum::extract::{Path, Query, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, put};
+    use axum::{Json, Router};
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_parse_list_ids() {
+        assert_eq!(parse_list_ids(" 2, 5,"), Ok(vec![2, 5]));
+        assert_eq!(parse_list_ids(" "), Err("No recipient lists".to_string()));
+        assert_eq!(
+            parse_list_ids("2, news"),
+            Err("Invalid list ID news".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number::<u32>("BREVO_DELAY", " 10 "), Ok(10));
+        assert_eq!(
+            parse_number::<u32>("BREVO_DELAY", "-5"),
+            Err("Invalid BREVO_DELAY: -5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scheduled_at() {
+        let brevo = Brevo::new(String::new(), None);
+        let later = Utc::now() + chrono::Duration::days(1);
+        let earlier = Utc::now() - chrono::Duration::days(1);
+
+        assert_eq!(brevo.scheduled_at(&Notify::Never, true), None);
+        assert_eq!(brevo.scheduled_at(&Notify::At(later), false), Some(later));
+        assert!(brevo.scheduled_at(&Notify::At(earlier), true).unwrap() > Utc::now());
+        assert!(
+            brevo.scheduled_at(&Notify::AfterDelay, false).unwrap()
+                > Utc::now() + chrono::Duration::minutes(4)
+        );
+    }
+
+    type Stored = Arc<Mutex<Vec<Value>>>;
+
+    fn authorized(headers: &HeaderMap) -> Result<(), (StatusCode, Json<Value>)> {
+        match headers.get("api-key").and_then(|v| v.to_str().ok()) {
+            Some("secret") => Ok(()),
+            _ => Err((
+                StatusCode::UNAUTHORIZED,
+                Json(json!({ "code": "unauthorized", "message": "Key not found" })),
+            )),
+        }
+    }
+
+    // Keeps campaigns like Brevo does, with an id and a status
+    async fn mock_brevo() -> (String, Stored) {
+        let campaigns = Stored::default();
+        let app = Router::new()
+            .route(
+                "/emailCampaigns",
+                get(
+                    |State(stored): State<Stored>,
+                     headers: HeaderMap,
+                     Query(query): Query<HashMap<String, String>>| async move {
+                        authorized(&headers)?;
+                        let stored = stored.lock().unwrap();
+                        // Newest first and without params, like Brevo lists them
+                        let campaigns: Vec<Value> = stored
+                            .iter()
+                            .rev()
+                            .skip(query["offset"].parse().unwrap())
+                            .take(query["limit"].parse().unwrap())
+                            .map(|campaign| {
+                                json!({
+                                    "id": campaign["id"],
+                                    "name": campaign["name"],
+                                    "subject": campaign["subject"],
+                                    "type": "classic",
+                                    "status": campaign["status"],
+                                    "tag": campaign["tag"],
+                                    "scheduledAt": campaign["scheduledAt"],
+                                })
+                            })
+                            .collect();
+                        Ok::<_, (StatusCode, Json<Value>)>(Json(
+                            json!({ "campaigns": campaigns, "count": stored.len() }),
+                        ))
+                    },
+                )
+                .post(
+                    |State(stored): State<Stored>,
+                     headers: HeaderMap,
+                     Json(mut campaign): Json<Value>| async move {
+                        authorized(&headers)?;
+                        let mut stored = stored.lock().unwrap();
+                        let id = stored.len() + 1;
+                        campaign["id"] = json!(id);
+                        campaign["status"] = json!("queued");
+                        stored.push(campaign);
+                        Ok::<_, (StatusCode, Json<Value>)>((
+                            StatusCode::CREATED,
+                            Json(json!({ "id": id })),
+                        ))
+                    },
+                ),
+            )
+            .route(
+                "/emailCampaigns/{id}",
+                put(
+                    |State(stored): State<Stored>,
+                     Path(id): Path<usize>,
+                     headers: HeaderMap,
+                     Json(mut campaign): Json<Value>| async move {
+                        authorized(&headers)?;
+                        let mut stored = stored.lock().unwrap();
+                        campaign["id"] = json!(id);
+                        campaign["status"] = stored[id - 1]["status"].clone();
+                        stored[id - 1] = campaign;
+                        // Brevo answers 204, a body must not make the update look like a new campaign
+                        Ok::<_, (StatusCode, Json<Value>)>(Json(json!({ "id": id })))
+                    },
+                ),
+            )
+            .with_state(campaigns.clone());
+
+        let url = test_server::serve(app).await;
+        (url, campaigns)
+    }
+
+    fn newsletter(list_ids: Option<Vec<i64>>) -> Newsletter {
+        Newsletter {
+            title: "Test Post".to_string(),
+            description: "A ride".to_string(),
+            image_url: "https://example.com/image.jpg".to_string(),
+            post_url: "https://example.com/test-post".to_string(),
+            list_ids,
+            scheduled_at: "2023-10-01T16:00:00Z".parse().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_campaign() {
+        let (url, campaigns) = mock_brevo().await;
+        let brevo = Brevo {
+            sender_id: 3,
+            template_id: 7,
+            ..Brevo::new(url, Some("secret".to_string()))
+        };
+
+        assert_eq!(
+            brevo.post_campaign(&newsletter(None)).await,
+            Ok(Campaign::Created(1))
+        );
+        assert_eq!(
+            campaigns.lock().unwrap()[0],
+            json!({
+                "id": 1,
+                "status": "queued",
+                "tag": "plog:https://example.com/test-post",
+                "name": "Test Post",
+                "subject": "Test Post",
+                "sender": { "id": 3 },
+                "recipients": { "listIds": [2] },
+                "templateId": 7,
+                "params": {
+                    "TITLE": "Test Post",
+                    "DESCRIPTION": "A ride",
+                    "IMAGE_URL": "https://example.com/image.jpg",
+                    "POST_URL": "https://example.com/test-post",
+                },
+                "scheduledAt": "2023-10-01T16:00:00+00:00",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_campaign_again() {
+        let (url, campaigns) = mock_brevo().await;
+        let brevo = Brevo::new(url, Some("secret".to_string()));
+        brevo.post_campaign(&newsletter(None)).await.unwrap();
+
+        assert_eq!(
+            brevo.post_campaign(&newsletter(Some(vec![4, 5]))).await,
+            Ok(Campaign::Updated)
+        );
+        assert_eq!(campaigns.lock().unwrap().len(), 1);
+        assert_eq!(
+            campaigns.lock().unwrap()[0]["recipients"],
+            json!({ "listIds": [4, 5] })
+        );
+
+        campaigns.lock().unwrap()[0]["status"] = json!("sent");
+        assert_eq!(
+            brevo.post_campaign(&newsletter(None)).await,
+            Ok(Campaign::AlreadySent(1))
+        );
+
+        let other = Newsletter {
+            post_url: "https://example.com/other-post".to_string(),
+            ..newsletter(None)
+        };
+        assert_eq!(brevo.post_campaign(&other).await, Ok(Campaign::Created(2)));
+    }
+
+    #[tokio::test]
+    async fn test_post_campaign_finds_older_campaigns() {
+        let (url, campaigns) = mock_brevo().await;
+        let brevo = Brevo::new(url, Some("secret".to_string()));
+        brevo.post_campaign(&newsletter(None)).await.unwrap();
+        campaigns.lock().unwrap()[0]["status"] = json!("sent");
+        for id in 2..=150 {
+            campaigns.lock().unwrap().push(json!({
+                "id": id,
+                "status": "sent",
+                "tag": format!("plog:https://example.com/post-{id}"),
+            }));
+        }
+
+        assert_eq!(
+            brevo.post_campaign(&newsletter(None)).await,
+            Ok(Campaign::AlreadySent(1))
+        );
+        assert_eq!(campaigns.lock().unwrap().len(), 150);
+    }
+
+    #[tokio::test]
+    async fn test_post_campaign_fails() {
+        let (url, _) = mock_brevo().await;
+
+        assert_eq!(
+            Brevo::new(url.clone(), Some("wrong".to_string()))
+                .post_campaign(&newsletter(None))
+                .await,
+            Err("Failed to list campaigns: 401 Unauthorized: Key not found".to_string())
+        );
+        assert_eq!(
+            Brevo::new(url, None).post_campaign(&newsletter(None)).await,
+            Err("BREVO_API_KEY not set".to_string())
         );
-        Err(format!(
-            "Failed to post campaign: {}: {}",
-            status, error.message
-        ))
     }
 }
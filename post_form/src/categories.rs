@@ -0,0 +1,124 @@
+use crate::meta;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Repository path of the per-category template and front matter configuration.
+pub const CATEGORIES_PATH: &str = "_plogtion/categories.yml";
+
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CategorySchema {
+    /// Template file in `_plogtion/` used instead of the default post template.
+    pub template: Option<String>,
+    #[serde(default)]
+    pub required: Vec<String>,
+    #[serde(default)]
+    pub optional: Vec<String>,
+}
+
+impl CategorySchema {
+    pub fn allows(&self, field: &str) -> bool {
+        self.required
+            .iter()
+            .chain(&self.optional)
+            .any(|f| f == field)
+    }
+}
+
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(transparent)]
+pub struct Categories(BTreeMap<String, CategorySchema>);
+
+impl Categories {
+    pub fn parse(yaml: &str) -> Result<Self, String> {
+        let categories: Categories = serde_yaml::from_str(yaml)
+            .map_err(|err| format!("Invalid {CATEGORIES_PATH}: {err}"))?;
+        for (category, schema) in &categories.0 {
+            if let Some(field) = schema
+                .required
+                .iter()
+                .chain(&schema.optional)
+                .find(|field| meta::is_reserved(field))
+            {
+                return Err(format!(
+                    "Invalid {CATEGORIES_PATH}: field {field} of {category} would replace {field}"
+                ));
+            }
+        }
+        Ok(categories)
+    }
+
+    /// The schemas of the configured categories among `categories`, in the post's order.
+    pub fn for_post<'a>(&'a self, categories: &'a [String]) -> Vec<(&'a str, &'a CategorySchema)> {
+        categories
+            .iter()
+            .filter_map(|category| Some((category.as_str(), self.0.get(category)?)))
+            .collect()
+    }
+
+    /// The template of the first of the post's categories that has one.
+    pub fn template<'a>(&'a self, categories: &'a [String]) -> Option<(&'a str, &'a str)> {
+        self.for_post(categories)
+            .into_iter()
+            .find_map(|(category, schema)| Some((category, schema.template.as_deref()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::parse_terms;
+    use pretty_assertions::assert_eq;
+
+    const CONFIG: &str = r#"
+bikepacking:
+  template: bikepacking.md.tera
+  required: [distance, elevation]
+  optional: [nights]
+hiking:
+  required: [summit]
+city-trip: {}
+"#;
+
+    #[test]
+    fn test_for_post() {
+        let categories = Categories::parse(CONFIG).unwrap();
+
+        let post_categories = parse_terms("city-trip, unknown, hiking");
+
+        let names: Vec<_> = categories
+            .for_post(&post_categories)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["city-trip", "hiking"]);
+    }
+
+    #[test]
+    fn test_template() {
+        let categories = Categories::parse(CONFIG).unwrap();
+
+        assert_eq!(
+            categories.template(&parse_terms("hiking, bikepacking")),
+            Some(("bikepacking", "bikepacking.md.tera"))
+        );
+        assert_eq!(categories.template(&parse_terms("hiking, city-trip")), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_reserved_fields() {
+        assert_eq!(
+            Categories::parse("hiking:\n  optional: [summit, layout]\n"),
+            Err(
+                "Invalid _plogtion/categories.yml: field layout of hiking would replace layout"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        let err = Categories::parse("hiking:\n  requried: [summit]\n").unwrap_err();
+        assert!(err.contains("unknown field `requried`"), "{err}");
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// What <input type="datetime-local"> sends, and the same with a space or seconds
+const LOCAL_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+];
+
+/// When a post happened, either a whole day or a moment in a timezone.
+/// Jekyll orders posts on the same day by their time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostDate {
+    Day(NaiveDate),
+    Time(DateTime<FixedOffset>),
+}
+
+impl PostDate {
+    /// Parses `2023-10-01`, `2023-10-01T14:30` in `timezone`, or a time with an offset.
+    pub fn parse(text: &str, timezone: Tz) -> Result<Self, String> {
+        let text = text.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(text)
+            .or_else(|_| DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S %z"))
+        {
+            return Ok(PostDate::Time(time));
+        }
+        for format in LOCAL_FORMATS {
+            if let Ok(local) = NaiveDateTime::parse_from_str(text, format) {
+                let time = timezone
+                    .from_local_datetime(&local)
+                    .single()
+                    .ok_or_else(|| format!("Time {text} is skipped or repeated in {timezone}"))?;
+                return Ok(PostDate::Time(time.fixed_offset()));
+            }
+        }
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .map(PostDate::Day)
+            .map_err(|_| format!("Invalid date {text}, use YYYY-MM-DD or YYYY-MM-DDTHH:MM"))
+    }
+
+    pub fn day(&self) -> NaiveDate {
+        match self {
+            PostDate::Day(day) => *day,
+            PostDate::Time(time) => time.date_naive(),
+        }
+    }
+}
+
+/// Formats the date the way Jekyll reads it from front matter.
+impl fmt::Display for PostDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PostDate::Day(day) => write!(f, "{}", day.format("%Y-%m-%d")),
+            PostDate::Time(time) => write!(f, "{}", time.format("%Y-%m-%d %H:%M:%S %z")),
+        }
+    }
+}
+
+impl Serialize for PostDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PostDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        PostDate::parse(&text, Tz::UTC).map_err(serde::de::Error::custom)
+    }
+}
+
+// TIMEZONE is where times without an offset happened, like Europe/Oslo
+pub fn timezone_from_env() -> Result<Tz, String> {
+    match std::env::var("TIMEZONE") {
+        Ok(name) => name
+            .trim()
+            .parse()
+            .map_err(|_| format!("Unknown timezone: {name}")),
+        Err(_) => Ok(Tz::UTC),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse() {
+        let oslo: Tz = "Europe/Oslo".parse().unwrap();
+        let cases = vec![
+            ("2023-10-01", Ok("2023-10-01")),
+            (" 2023-10-01T14:30 ", Ok("2023-10-01 14:30:00 +0200")),
+            ("2023-12-01 08:05:09", Ok("2023-12-01 08:05:09 +0100")),
+            ("2023-10-01T14:30:00Z", Ok("2023-10-01 14:30:00 +0000")),
+            ("2023-10-01 14:30:00 -0700", Ok("2023-10-01 14:30:00 -0700")),
+            (
+                "2023-03-26T02:30",
+                Err("Time 2023-03-26T02:30 is skipped or repeated in Europe/Oslo"),
+            ),
+            (
+                "01.10.2023",
+                Err("Invalid date 01.10.2023, use YYYY-MM-DD or YYYY-MM-DDTHH:MM"),
+            ),
+            (
+                "2023-02-30",
+                Err("Invalid date 2023-02-30, use YYYY-MM-DD or YYYY-MM-DDTHH:MM"),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                PostDate::parse(input, oslo).map(|date| date.to_string()),
+                expected.map(String::from).map_err(String::from),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_day_keeps_the_local_day() {
+        let date = PostDate::parse("2023-10-01 00:30:00 +0200", Tz::UTC).unwrap();
+
+        assert_eq!(date.day(), NaiveDate::from_ymd_opt(2023, 10, 1).unwrap());
+    }
+}
@@ -0,0 +1,189 @@
+use crate::tera::ImageMetadata;
+use chrono::NaiveDate;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+/// Where the location links in posts point.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MapProvider {
+    #[default]
+    Google,
+    OpenStreetMap,
+}
+
+impl MapProvider {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "google" => Ok(MapProvider::Google),
+            "openstreetmap" | "osm" => Ok(MapProvider::OpenStreetMap),
+            other => Err(format!("Unknown map provider: {other}")),
+        }
+    }
+
+    // MAP_PROVIDER picks the map the post template links locations to
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("MAP_PROVIDER") {
+            Ok(name) => MapProvider::parse(&name),
+            Err(_) => Ok(MapProvider::default()),
+        }
+    }
+
+    pub fn url(&self, latitude: f64, longitude: f64) -> String {
+        match self {
+            MapProvider::Google => {
+                format!("https://www.google.com/maps/place/{latitude},{longitude}")
+            }
+            MapProvider::OpenStreetMap => format!(
+                "https://www.openstreetmap.org/?mlat={latitude}&mlon={longitude}#map=15/{latitude}/{longitude}"
+            ),
+        }
+    }
+}
+
+/// Parses coordinates written as `latitude,longitude`.
+pub fn parse_coordinates(coordinates: &str) -> Option<(f64, f64)> {
+    let (latitude, longitude) = coordinates.split_once(',')?;
+    Some((
+        latitude.trim().parse().ok()?,
+        longitude.trim().parse().ok()?,
+    ))
+}
+
+pub fn geojson_path(date: NaiveDate, slug: &str) -> String {
+    format!("assets/geo/{date}-{slug}.geojson")
+}
+
+pub fn has_points(images: &HashMap<String, ImageMetadata>) -> bool {
+    images
+        .values()
+        .any(|image| image.latitude.is_some() && image.longitude.is_some())
+}
+
+/// A FeatureCollection with a point for every image with a location, in the post's order.
+pub fn feature_collection(images: &HashMap<String, ImageMetadata>) -> Option<Value> {
+    if !has_points(images) {
+        return None;
+    }
+
+    let mut keys: Vec<&String> = images.keys().collect();
+    keys.sort();
+
+    let features: Vec<Value> = keys
+        .into_iter()
+        .filter_map(|key| {
+            let image = &images[key];
+            Some(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    // GeoJSON puts longitude first
+                    "coordinates": [image.longitude?, image.latitude?],
+                },
+                "properties": {
+                    "caption": image.caption,
+                    "image_url": image.image_url,
+                },
+            }))
+        })
+        .collect();
+
+    Some(json!({
+        "type": "FeatureCollection",
+        "features": features,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_feature_collection() {
+        let images = HashMap::from([
+            (
+                "b.jpg".to_string(),
+                ImageMetadata {
+                    caption: "Summit".to_string(),
+                    image_url: "https://example.com/b.jpg".to_string(),
+                    latitude: Some(61.6363),
+                    longitude: Some(8.3125),
+                    ..Default::default()
+                },
+            ),
+            (
+                "a.jpg".to_string(),
+                ImageMetadata {
+                    caption: "Start".to_string(),
+                    image_url: "https://example.com/a.jpg".to_string(),
+                    latitude: Some(60.3913),
+                    longitude: Some(5.3221),
+                    ..Default::default()
+                },
+            ),
+            (
+                "c.jpg".to_string(),
+                ImageMetadata {
+                    image_url: "https://example.com/c.jpg".to_string(),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        assert_eq!(
+            feature_collection(&images),
+            Some(json!({
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": { "type": "Point", "coordinates": [5.3221, 60.3913] },
+                        "properties": { "caption": "Start", "image_url": "https://example.com/a.jpg" },
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": { "type": "Point", "coordinates": [8.3125, 61.6363] },
+                        "properties": { "caption": "Summit", "image_url": "https://example.com/b.jpg" },
+                    },
+                ],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_map_provider_url() {
+        let cases = vec![
+            ("google", "https://www.google.com/maps/place/60.3913,5.3221"),
+            (
+                "OpenStreetMap",
+                "https://www.openstreetmap.org/?mlat=60.3913&mlon=5.3221#map=15/60.3913/5.3221",
+            ),
+        ];
+
+        for (name, expected) in cases {
+            let provider = MapProvider::parse(name).unwrap();
+            assert_eq!(provider.url(60.3913, 5.3221), expected);
+        }
+        assert_eq!(
+            MapProvider::parse("bing"),
+            Err("Unknown map provider: bing".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_coordinates() {
+        assert_eq!(
+            parse_coordinates("60.3913, 5.3221"),
+            Some((60.3913, 5.3221))
+        );
+        assert_eq!(parse_coordinates("60.3913"), None);
+        assert_eq!(parse_coordinates("north,east"), None);
+    }
+
+    #[test]
+    fn test_feature_collection_without_locations() {
+        let images = HashMap::from([("a.jpg".to_string(), ImageMetadata::default())]);
+
+        assert_eq!(feature_collection(&images), None);
+    }
+}
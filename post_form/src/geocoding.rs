@@ -0,0 +1,399 @@
+use async_trait::async_trait;
+use log::info;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The address parts of a location, as sent by the form or returned by Nominatim.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Geocoding {
+    pub suburb: String,
+    pub village: String,
+    pub town: String,
+    pub city: String,
+    pub municipality: String,
+    pub county: String,
+    pub state: String,
+    pub province: String,
+    pub country: String,
+}
+
+impl Geocoding {
+    fn part(&self, name: &str) -> &str {
+        match name {
+            "suburb" => &self.suburb,
+            "village" => &self.village,
+            "town" => &self.town,
+            "city" => &self.city,
+            "municipality" => &self.municipality,
+            "county" => &self.county,
+            "state" => &self.state,
+            "province" => &self.province,
+            "country" => &self.country,
+            _ => "",
+        }
+    }
+
+    /// Whether no address part is set, like when the form sends `{}`.
+    pub fn is_empty(&self) -> bool {
+        PARTS.iter().all(|name| self.part(name).trim().is_empty())
+    }
+
+    /// Joins the parts named by `format`, skipping empty parts and names already included,
+    /// so a city that is also its own municipality only shows up once.
+    pub fn format(&self, format: &LocationFormat) -> String {
+        let mut parts: Vec<&str> = vec![];
+        for name in &format.0 {
+            let part = self.part(name).trim();
+            if !part.is_empty() && !parts.iter().any(|known| known.eq_ignore_ascii_case(part)) {
+                parts.push(part);
+            }
+        }
+        parts.join(", ")
+    }
+}
+
+const PARTS: [&str; 9] = [
+    "suburb",
+    "village",
+    "town",
+    "city",
+    "municipality",
+    "county",
+    "state",
+    "province",
+    "country",
+];
+
+/// The address parts shown for a location, in order, like `town, country`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocationFormat(Vec<String>);
+
+impl Default for LocationFormat {
+    fn default() -> Self {
+        LocationFormat::parse("suburb, town, city, municipality, province, country").unwrap()
+    }
+}
+
+impl LocationFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let parts: Vec<String> = format
+            .split(',')
+            .map(|part| part.trim().to_lowercase())
+            .filter(|part| !part.is_empty())
+            .collect();
+        if parts.is_empty() {
+            return Err("Location format cannot be empty".to_string());
+        }
+        if let Some(unknown) = parts.iter().find(|part| !PARTS.contains(&part.as_str())) {
+            return Err(format!(
+                "Unknown location part {unknown}, use one of {}",
+                PARTS.join(", ")
+            ));
+        }
+        Ok(LocationFormat(parts))
+    }
+
+    // LOCATION_FORMAT picks the address parts shown for image locations
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("LOCATION_FORMAT") {
+            Ok(format) => LocationFormat::parse(&format),
+            Err(_) => Ok(LocationFormat::default()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Reverse {
+    address: Option<Geocoding>,
+    error: Option<String>,
+}
+
+// Photos from the same spot share coordinates. The oldest lookups are dropped first when it is full.
+static CACHE: Mutex<VecDeque<(String, Geocoding)>> = Mutex::new(VecDeque::new());
+const CACHE_SIZE: usize = 500;
+
+// Nominatim's usage policy allows one request per second, also across concurrent posts
+static LAST_REQUEST: tokio::sync::Mutex<Option<Instant>> = tokio::sync::Mutex::const_new(None);
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+// Nominatim's usage policy requires identifying the application and a way to contact its owner
+const USER_AGENT: &str = "plogtion (+https://github.com/Kyrremann/plogtion)";
+
+// Five decimals is about a meter, closer than any photo location is accurate
+fn cache_key(latitude: f64, longitude: f64) -> String {
+    format!("{latitude:.5},{longitude:.5}")
+}
+
+#[async_trait]
+pub trait ReverseGeocoder: Send + Sync {
+    async fn reverse(&self, latitude: f64, longitude: f64) -> Result<Geocoding, String>;
+}
+
+/// A client for the reverse endpoint of Nominatim, or any service with the same API.
+pub struct Nominatim {
+    client: reqwest::Client,
+    api_url: String,
+    /// Preferred language of the names, like `en` or `nb`, instead of the local names.
+    language: Option<String>,
+    user_agent: String,
+}
+
+impl Nominatim {
+    pub fn new(api_url: String, language: Option<String>) -> Self {
+        Nominatim {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            language,
+            user_agent: USER_AGENT.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl ReverseGeocoder for Nominatim {
+    async fn reverse(&self, latitude: f64, longitude: f64) -> Result<Geocoding, String> {
+        let key = cache_key(latitude, longitude);
+        let language = self.language.as_deref().unwrap_or_default();
+        let cache_key = format!("{key}:{language}");
+        if let Some(geocoding) = cached(&cache_key) {
+            return Ok(geocoding);
+        }
+
+        // Held until the request is done, so the next one waits its turn
+        let mut last_request = LAST_REQUEST.lock().await;
+        if let Some(geocoding) = cached(&cache_key) {
+            return Ok(geocoding);
+        }
+        if let Some(last_request) = *last_request {
+            tokio::time::sleep(REQUEST_INTERVAL.saturating_sub(last_request.elapsed())).await;
+        }
+
+        let mut request = self
+            .client
+            .get(format!("{}/reverse", self.api_url))
+            .query(&[
+                ("format", "jsonv2"),
+                ("lat", &latitude.to_string()),
+                ("lon", &longitude.to_string()),
+            ]);
+        if let Some(language) = &self.language {
+            request = request.query(&[("accept-language", language)]);
+        }
+        let response = request.header("User-Agent", &self.user_agent).send().await;
+        *last_request = Some(Instant::now());
+        let response = response.map_err(|err| format!("Request failed: {err}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to geocode {key}: {status}: {body}"));
+        }
+
+        let reverse: Reverse = response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse geocoding of {key}: {err}"))?;
+        let geocoding = match (reverse.address, reverse.error) {
+            (Some(address), _) => address,
+            (None, Some(err)) => return Err(format!("Failed to geocode {key}: {err}")),
+            (None, None) => return Err(format!("Failed to geocode {key}: No address")),
+        };
+
+        info!("Geocoded {key} as {geocoding:?}");
+        let mut cache = CACHE.lock().unwrap();
+        if cache.len() >= CACHE_SIZE {
+            cache.pop_front();
+        }
+        cache.push_back((cache_key, geocoding.clone()));
+        Ok(geocoding)
+    }
+}
+
+fn cached(key: &str) -> Option<Geocoding> {
+    CACHE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(cached, _)| cached == key)
+        .map(|(_, geocoding)| geocoding.clone())
+}
+
+// GEOCODING_API_URL points reverse geocoding at another Nominatim instance,
+// GEOCODING_LANGUAGE asks for names in that language and GEOCODING_USER_AGENT identifies the plog
+pub fn from_env() -> Box<dyn ReverseGeocoder> {
+    let api_url = std::env::var("GEOCODING_API_URL")
+        .unwrap_or_else(|_| "https://nominatim.openstreetmap.org".to_string());
+    let language = std::env::var("GEOCODING_LANGUAGE").ok();
+    let mut nominatim = Nominatim::new(api_url, language);
+    if let Ok(user_agent) = std::env::var("GEOCODING_USER_AGENT") {
+        nominatim.user_agent = user_agent;
+    }
+    Box::new(nominatim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use axum::extract::{Query, State};
+    use axum::http::HeaderMap;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn mock_nominatim() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/reverse",
+                get(
+                    |State(requests): State<Arc<AtomicUsize>>,
+                     headers: HeaderMap,
+                     Query(query): Query<HashMap<String, String>>| async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        if headers.get("user-agent").and_then(|v| v.to_str().ok())
+                            != Some(USER_AGENT)
+                        {
+                            return Json(json!({ "error": "Missing User-Agent" }));
+                        }
+                        match query["lat"].as_str() {
+                            "60.3913"
+                                if query.get("accept-language").map(String::as_str)
+                                    == Some("en") =>
+                            {
+                                Json(json!({
+                                    "address": {
+                                        "city": "Bergen",
+                                        "state": "Vestland",
+                                        "country": "Norway",
+                                    },
+                                }))
+                            }
+                            "60.3913" => Json(json!({
+                                "display_name": "Bergenhus, Bergen, Vestland, Norge",
+                                "address": {
+                                    "suburb": "Bergenhus",
+                                    "city": "Bergen",
+                                    "state": "Vestland",
+                                    "country": "Norge",
+                                    "country_code": "no",
+                                },
+                            })),
+                            _ => Json(json!({ "error": "Unable to geocode" })),
+                        }
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+
+        let url = test_server::serve(app).await;
+        (url, requests)
+    }
+
+    #[test]
+    fn test_format() {
+        let geocoding = Geocoding {
+            suburb: "Bergenhus".to_string(),
+            city: "Bergen".to_string(),
+            municipality: "bergen".to_string(),
+            county: "Bergen".to_string(),
+            state: "Vestland".to_string(),
+            country: "Norway".to_string(),
+            ..Default::default()
+        };
+        let cases = vec![
+            (
+                "suburb, town, city, municipality, province, country",
+                "Bergenhus, Bergen, Norway",
+            ),
+            ("town, country", "Norway"),
+            ("City, county, state", "Bergen, Vestland"),
+        ];
+
+        for (format, expected) in cases {
+            let format = LocationFormat::parse(format).unwrap();
+            assert_eq!(geocoding.format(&format), expected);
+        }
+        assert_eq!(Geocoding::default().format(&LocationFormat::default()), "");
+        assert!(Geocoding::default().is_empty());
+        assert!(!geocoding.is_empty());
+    }
+
+    #[test]
+    fn test_parse_location_format() {
+        assert_eq!(
+            LocationFormat::parse(" , "),
+            Err("Location format cannot be empty".to_string())
+        );
+        assert_eq!(
+            LocationFormat::parse("town, planet"),
+            Err("Unknown location part planet, use one of suburb, village, town, city, municipality, county, state, province, country".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reverse_is_cached() {
+        let (url, requests) = mock_nominatim().await;
+        let geocoder = Nominatim::new(url, None);
+
+        let expected = Geocoding {
+            suburb: "Bergenhus".to_string(),
+            city: "Bergen".to_string(),
+            state: "Vestland".to_string(),
+            country: "Norge".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            geocoder.reverse(60.3913, 5.3221).await,
+            Ok(expected.clone())
+        );
+        assert_eq!(geocoder.reverse(60.391301, 5.3221).await, Ok(expected));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_is_throttled() {
+        let (url, requests) = mock_nominatim().await;
+        let geocoder = Nominatim::new(url, None);
+
+        let started = Instant::now();
+        assert!(geocoder.reverse(1.5, 1.5).await.is_err());
+        assert!(geocoder.reverse(2.5, 2.5).await.is_err());
+        assert!(started.elapsed() >= REQUEST_INTERVAL);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_in_language() {
+        let (url, _) = mock_nominatim().await;
+        let geocoder = Nominatim::new(url, Some("en".to_string()));
+
+        assert_eq!(
+            geocoder.reverse(60.3913, 5.3221).await,
+            Ok(Geocoding {
+                city: "Bergen".to_string(),
+                state: "Vestland".to_string(),
+                country: "Norway".to_string(),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reverse_unable_to_geocode() {
+        let (url, _) = mock_nominatim().await;
+        let geocoder = Nominatim::new(url, None);
+
+        assert_eq!(
+            geocoder.reverse(0.5, -30.5).await,
+            Err("Failed to geocode 0.50000,-30.50000: Unable to geocode".to_string())
+        );
+    }
+}
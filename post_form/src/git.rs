@@ -1,17 +1,90 @@
-use git2::{Cred, PushOptions, RemoteCallbacks, Repository, Signature, build::RepoBuilder};
-use log::info;
+use crate::publisher::{Changes, File, Publisher};
+use async_trait::async_trait;
+use git2::{
+    Cred, ErrorCode, FetchOptions, ObjectType, Oid, PushOptions, RemoteCallbacks, Repository,
+    ResetType, Signature, build::RepoBuilder,
+};
+use log::{error, info, warn};
+use std::cell::RefCell;
 use std::path::Path;
+use tempfile::TempDir;
 
-const REPO_PATH: &str = "./plog";
+pub const REPO_URL: &str = "https://github.com/Kyrremann/plog.git";
+const MAX_PUSH_ATTEMPTS: usize = 3;
 
-pub async fn clone_repository(token: &str) -> Result<Repository, String> {
-    // Clean up the temporary directory if it exists
-    if Path::new(REPO_PATH).exists() {
-        std::fs::remove_dir_all(REPO_PATH)
-            .map_err(|e| format!("Failed to clean up temporary directory: {e}"))?;
+enum PushError {
+    Rejected(String),
+    Failed(String),
+}
+
+/// Publishes posts by committing to a local clone of the repository and pushing it.
+/// Every publisher clones into its own temporary directory, which is removed when it is dropped.
+pub struct GitPublisher {
+    token: String,
+    dir: TempDir,
+}
+
+impl GitPublisher {
+    pub async fn clone(url: &str, token: &str) -> Result<Self, String> {
+        let dir = tempfile::Builder::new()
+            .prefix("plog-")
+            .tempdir()
+            .map_err(|e| format!("Failed to create temporary directory: {e}"))?;
+        clone_repository(url, token, dir.path()).await?;
+        Ok(GitPublisher {
+            token: token.to_string(),
+            dir,
+        })
+    }
+}
+
+#[async_trait]
+impl Publisher for GitPublisher {
+    async fn read_file(&self, file_path: &str) -> Result<Option<String>, String> {
+        match std::fs::read_to_string(self.dir.path().join(file_path)) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("Failed to read {file_path}: {err}")),
+        }
+    }
+
+    // Lists what was cloned, from the HEAD tree rather than the working directory
+    async fn list_files(&self, dir: &str) -> Result<Vec<File>, String> {
+        let repo = Repository::open(self.dir.path())
+            .map_err(|e| format!("Failed to open repository: {e}"))?;
+        let head = repo
+            .head()
+            .and_then(|head| head.peel_to_tree())
+            .map_err(|e| format!("Failed to read the HEAD tree: {e}"))?;
+        let tree = match head.get_path(Path::new(dir)) {
+            Ok(entry) => entry
+                .to_object(&repo)
+                .and_then(|object| object.peel_to_tree())
+                .map_err(|e| format!("Failed to list {dir}: {e}"))?,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(vec![]),
+            Err(e) => return Err(format!("Failed to list {dir}: {e}")),
+        };
+
+        let mut files: Vec<File> = tree
+            .iter()
+            .filter(|entry| entry.kind() == Some(ObjectType::Blob))
+            .map(|entry| File {
+                path: format!("{dir}/{}", String::from_utf8_lossy(entry.name_bytes())),
+                id: entry.id().to_string(),
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
     }
 
-    let url = "https://github.com/Kyrremann/plog.git";
+    async fn publish(&self, changes: &Changes, message: &str) -> Result<String, String> {
+        let repo = Repository::open(self.dir.path())
+            .map_err(|e| format!("Failed to open repository: {e}"))?;
+        commit_and_push(repo, &self.token, changes, message).await
+    }
+}
+
+pub async fn clone_repository(url: &str, token: &str, path: &Path) -> Result<Repository, String> {
     let mut fetch_options = git2::FetchOptions::new();
     let mut callbacks = RemoteCallbacks::new();
     callbacks.credentials(|_, _, _| Cred::userpass_plaintext(token, ""));
@@ -19,32 +92,56 @@ pub async fn clone_repository(token: &str) -> Result<Repository, String> {
 
     let repo = RepoBuilder::new()
         .fetch_options(fetch_options)
-        .clone(url, Path::new(REPO_PATH))
+        .clone(url, path)
         .map_err(|e| format!("Failed to clone repository with RepoBuilder: {e}"))?;
 
-    info!("Repository cloned successfully");
+    info!("Repository cloned successfully into {}", path.display());
     Ok(repo)
 }
 
+/// Writes and deletes all of `changes` as a single commit, pushes it and returns the SHA of the pushed commit.
 pub async fn commit_and_push(
     repo: Repository,
     token: &str,
-    file_path: &str,
+    changes: &Changes,
     message: &str,
 ) -> Result<String, String> {
-    // Ensure file to commit exists
-    let file_to_commit = Path::new(REPO_PATH).join(file_path);
-    if !file_to_commit.exists() {
-        return Err(format!("File to commit does not exist: {file_path}"));
-    }
-
-    // Adding the change to the index
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| "Repository has no working directory".to_string())?
+        .to_path_buf();
     let mut index = repo
         .index()
         .map_err(|e| format!("Failed to get repository index: {e}"))?;
-    index
-        .add_path(Path::new(file_path))
-        .map_err(|e| format!("Failed to add file to index: {e}"))?;
+
+    // Check the deletes before touching the working directory
+    for file_path in &changes.delete {
+        if index.get_path(Path::new(file_path), 0).is_none() {
+            return Err(format!("File to delete is not tracked: {file_path}"));
+        }
+    }
+
+    for (file_path, content) in &changes.write {
+        let file_name = workdir.join(file_path);
+        if let Some(parent) = file_name.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|err| format!("Failed to create directory for {file_path}: {err}"))?;
+        }
+        std::fs::write(&file_name, content).map_err(|err| {
+            error!("Failed to write {file_path}: {err}");
+            format!("Failed to write {file_path}: {err}")
+        })?;
+        index
+            .add_path(Path::new(file_path))
+            .map_err(|e| format!("Failed to add {file_path} to index: {e}"))?;
+    }
+    for file_path in &changes.delete {
+        std::fs::remove_file(workdir.join(file_path))
+            .map_err(|err| format!("Failed to delete {file_path}: {err}"))?;
+        index
+            .remove_path(Path::new(file_path))
+            .map_err(|e| format!("Failed to remove {file_path} from index: {e}"))?;
+    }
     index
         .write()
         .map_err(|e| format!("Failed to write index: {e}"))?;
@@ -59,36 +156,397 @@ pub async fn commit_and_push(
         .find_tree(oid)
         .map_err(|e| format!("Failed to find tree: {e}"))?;
 
-    repo.commit(
-        Some("HEAD"),
-        &author,
-        &author,
-        message,
-        &tree,
-        &[&repo
-            .head()
-            .map_err(|e| format!("Failed to get repository head: {e}"))?
-            .peel_to_commit()
-            .map_err(|e| format!("Failed to peel to commit: {e}"))?],
-    )
-    .map_err(|e| format!("Failed to create commit: {e}"))?;
+    let mut commit_id = repo
+        .commit(
+            Some("HEAD"),
+            &author,
+            &author,
+            message,
+            &tree,
+            &[&repo
+                .head()
+                .map_err(|e| format!("Failed to get repository head: {e}"))?
+                .peel_to_commit()
+                .map_err(|e| format!("Failed to peel to commit: {e}"))?],
+        )
+        .map_err(|e| format!("Failed to create commit: {e}"))?;
+
+    // Someone may have pushed since we cloned, so replay our commit on top of theirs and retry
+    let mut last_reason = String::new();
+    for attempt in 1..=MAX_PUSH_ATTEMPTS {
+        match push(&repo, token) {
+            Ok(()) => {
+                info!("Changes pushed successfully as {commit_id}");
+                return Ok(commit_id.to_string());
+            }
+            Err(PushError::Rejected(reason)) => {
+                warn!("Push rejected on attempt {attempt}: {reason}");
+                last_reason = reason;
+                if attempt < MAX_PUSH_ATTEMPTS {
+                    commit_id = rebase_on_origin(&repo, token, commit_id)?;
+                }
+            }
+            Err(PushError::Failed(err)) => return Err(err),
+        }
+    }
+
+    Err(format!(
+        "Push was still rejected after {MAX_PUSH_ATTEMPTS} attempts: {last_reason}"
+    ))
+}
+
+fn push(repo: &Repository, token: &str) -> Result<(), PushError> {
+    let rejection = RefCell::new(None);
 
     let mut callbacks = RemoteCallbacks::new();
     callbacks.credentials(|_, _, _| Cred::userpass_plaintext(token, ""));
+    callbacks.push_update_reference(|_, status| {
+        if let Some(status) = status {
+            *rejection.borrow_mut() = Some(status.to_string());
+        }
+        Ok(())
+    });
     let mut push_options = PushOptions::new();
     push_options.remote_callbacks(callbacks);
 
     let mut remote = repo
         .find_remote("origin")
-        .map_err(|e| format!("Failed to find remote: {e}"))?;
+        .map_err(|e| PushError::Failed(format!("Failed to find remote: {e}")))?;
+
+    match remote.push(
+        &["refs/heads/main:refs/heads/main"],
+        Some(&mut push_options),
+    ) {
+        Err(e) if e.code() == ErrorCode::NotFastForward => {
+            return Err(PushError::Rejected(e.message().to_string()));
+        }
+        Err(e) => return Err(PushError::Failed(format!("Failed to push changes: {e}"))),
+        Ok(()) => {}
+    }
+    drop(push_options);
+
+    match rejection.into_inner() {
+        Some(reason) if is_behind(&reason) => Err(PushError::Rejected(reason)),
+        // Hooks and branch protection decline for reasons a rebase doesn't fix
+        Some(reason) => Err(PushError::Failed(format!("Push was declined: {reason}"))),
+        None => Ok(()),
+    }
+}
+
+// How servers say the branch moved on since we fetched
+fn is_behind(reason: &str) -> bool {
+    ["non-fast-forward", "fetch first", "stale info"]
+        .iter()
+        .any(|behind| reason.contains(behind))
+}
 
-    remote
-        .push(
-            &["refs/heads/main:refs/heads/main"],
-            Some(&mut push_options),
+/// Fetches origin/main and replays our single commit on top of it, failing only on real conflicts.
+fn rebase_on_origin(repo: &Repository, token: &str, commit_id: Oid) -> Result<Oid, String> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|_, _, _| Cred::userpass_plaintext(token, ""));
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+
+    repo.find_remote("origin")
+        .map_err(|e| format!("Failed to find remote: {e}"))?
+        .fetch(
+            &["refs/heads/main:refs/remotes/origin/main"],
+            Some(&mut fetch_options),
+            None,
         )
-        .map_err(|e| format!("Failed to push changes: {e}"))?;
+        .map_err(|e| format!("Failed to fetch origin: {e}"))?;
+
+    let upstream = repo
+        .find_reference("refs/remotes/origin/main")
+        .and_then(|reference| reference.peel_to_commit())
+        .map_err(|e| format!("Failed to find origin/main: {e}"))?;
+    let ours = repo
+        .find_commit(commit_id)
+        .map_err(|e| format!("Failed to find commit to rebase: {e}"))?;
 
-    info!("Changes pushed successfully");
-    Ok("Changes pushed successfully".to_string())
+    let mut index = repo
+        .cherrypick_commit(&ours, &upstream, 0, None)
+        .map_err(|e| format!("Failed to replay commit: {e}"))?;
+    if index.has_conflicts() {
+        let paths: Vec<String> = index
+            .conflicts()
+            .map_err(|e| format!("Failed to read conflicts: {e}"))?
+            .filter_map(|conflict| conflict.ok()?.our)
+            .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+            .collect();
+        return Err(format!(
+            "Conflict with upstream changes in: {}",
+            paths.join(", ")
+        ));
+    }
+
+    let tree = index
+        .write_tree_to(repo)
+        .and_then(|oid| repo.find_tree(oid))
+        .map_err(|e| format!("Failed to write rebased tree: {e}"))?;
+    let rebased = repo
+        .commit(
+            None,
+            &ours.author(),
+            &ours.committer(),
+            ours.message().unwrap_or_default(),
+            &tree,
+            &[&upstream],
+        )
+        .map_err(|e| format!("Failed to create rebased commit: {e}"))?;
+
+    let commit = repo
+        .find_commit(rebased)
+        .map_err(|e| format!("Failed to find rebased commit: {e}"))?;
+    repo.reset(commit.as_object(), ResetType::Hard, None)
+        .map_err(|e| format!("Failed to move main to rebased commit: {e}"))?;
+
+    info!("Rebased commit onto {}", upstream.id());
+    Ok(rebased)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::RepositoryInitOptions;
+
+    fn init_origin(dir: &Path) {
+        let mut options = RepositoryInitOptions::new();
+        options.bare(true).initial_head("main");
+        Repository::init_opts(dir.join("origin.git"), &options).unwrap();
+
+        let seed = clone(dir, "seed");
+        let author = Signature::now("Test", "test@example.com").unwrap();
+        let tree = {
+            let mut index = seed.index().unwrap();
+            let oid = index.write_tree().unwrap();
+            seed.find_tree(oid).unwrap()
+        };
+        seed.commit(Some("HEAD"), &author, &author, "Initial", &tree, &[])
+            .unwrap();
+        seed.find_remote("origin")
+            .unwrap()
+            .push(&["refs/heads/main:refs/heads/main"], None)
+            .unwrap();
+    }
+
+    fn clone(dir: &Path, name: &str) -> Repository {
+        let origin = dir.join("origin.git");
+        let repo = RepoBuilder::new()
+            .clone(origin.to_str().unwrap(), &dir.join(name))
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+        repo
+    }
+
+    async fn write_and_push(
+        repo: Repository,
+        file_path: &str,
+        content: &str,
+    ) -> Result<String, String> {
+        let changes = Changes {
+            write: vec![(file_path.to_string(), content.to_string())],
+            ..Default::default()
+        };
+        commit_and_push(repo, "", &changes, "Test commit").await
+    }
+
+    fn origin_file(dir: &Path, file_path: &str) -> Option<String> {
+        let origin = Repository::open_bare(dir.join("origin.git")).unwrap();
+        let tree = origin
+            .find_reference("refs/heads/main")
+            .unwrap()
+            .peel_to_tree()
+            .unwrap();
+        let entry = tree.get_path(Path::new(file_path)).ok()?;
+        let blob = origin.find_blob(entry.id()).unwrap();
+        Some(String::from_utf8(blob.content().to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_is_behind() {
+        assert!(is_behind("fetch first"));
+        assert!(is_behind("non-fast-forward"));
+        assert!(!is_behind("pre-receive hook declined"));
+        assert!(!is_behind("protected branch hook declined"));
+    }
+
+    #[tokio::test]
+    async fn test_commit_and_push_rebases_on_concurrent_push() {
+        let dir = TempDir::new().unwrap();
+        init_origin(dir.path());
+        let ours = clone(dir.path(), "ours");
+        let theirs = clone(dir.path(), "theirs");
+
+        write_and_push(theirs, "_posts/theirs.md", "Theirs")
+            .await
+            .unwrap();
+        write_and_push(ours, "_posts/ours.md", "Ours")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            origin_file(dir.path(), "_posts/theirs.md").as_deref(),
+            Some("Theirs")
+        );
+        assert_eq!(
+            origin_file(dir.path(), "_posts/ours.md").as_deref(),
+            Some("Ours")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_and_push_fails_on_conflict() {
+        let dir = TempDir::new().unwrap();
+        init_origin(dir.path());
+        let ours = clone(dir.path(), "ours");
+        let theirs = clone(dir.path(), "theirs");
+
+        write_and_push(theirs, "_posts/post.md", "Theirs")
+            .await
+            .unwrap();
+        let result = write_and_push(ours, "_posts/post.md", "Ours").await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            "Conflict with upstream changes in: _posts/post.md"
+        );
+        assert_eq!(
+            origin_file(dir.path(), "_posts/post.md").as_deref(),
+            Some("Theirs")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publishers_use_isolated_checkouts() {
+        let dir = TempDir::new().unwrap();
+        init_origin(dir.path());
+        let origin = dir.path().join("origin.git");
+        let origin = origin.to_str().unwrap();
+
+        let first = GitPublisher::clone(origin, "").await.unwrap();
+        let second = GitPublisher::clone(origin, "").await.unwrap();
+        let checkouts = [first.dir.path().to_owned(), second.dir.path().to_owned()];
+        assert_ne!(checkouts[0], checkouts[1]);
+
+        let changes = Changes {
+            write: vec![("_posts/first.md".to_string(), "First".to_string())],
+            ..Default::default()
+        };
+        first.publish(&changes, "First").await.unwrap();
+        let changes = Changes {
+            write: vec![
+                ("_posts/second.md".to_string(), "Second".to_string()),
+                ("_data/trips/tour.yml".to_string(), "posts: []".to_string()),
+            ],
+            ..Default::default()
+        };
+        second.publish(&changes, "Second").await.unwrap();
+        drop((first, second));
+
+        assert!(checkouts.iter().all(|checkout| !checkout.exists()));
+        assert_eq!(
+            origin_file(dir.path(), "_posts/first.md").as_deref(),
+            Some("First")
+        );
+        assert_eq!(
+            origin_file(dir.path(), "_posts/second.md").as_deref(),
+            Some("Second")
+        );
+        assert_eq!(
+            origin_file(dir.path(), "_data/trips/tour.yml").as_deref(),
+            Some("posts: []")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_files() {
+        let dir = TempDir::new().unwrap();
+        init_origin(dir.path());
+        write_and_push(clone(dir.path(), "seed-a"), "_posts/a.md", "A")
+            .await
+            .unwrap();
+        write_and_push(clone(dir.path(), "seed-b"), "_posts/drafts/b.md", "B")
+            .await
+            .unwrap();
+
+        let origin = dir.path().join("origin.git");
+        let publisher = GitPublisher::clone(origin.to_str().unwrap(), "")
+            .await
+            .unwrap();
+        let files = publisher.list_files("_posts").await.unwrap();
+
+        let paths: Vec<_> = files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["_posts/a.md"]);
+        assert_eq!(
+            files[0].id,
+            Oid::hash_object(ObjectType::Blob, b"A")
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(publisher.list_files("_data").await, Ok(vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_publish_adds_modifies_and_deletes_in_one_commit() {
+        let dir = TempDir::new().unwrap();
+        init_origin(dir.path());
+        write_and_push(clone(dir.path(), "seed-post"), "_posts/post.md", "Old")
+            .await
+            .unwrap();
+        write_and_push(
+            clone(dir.path(), "seed-geo"),
+            "assets/geo/post.geojson",
+            "{}",
+        )
+        .await
+        .unwrap();
+
+        let origin = dir.path().join("origin.git");
+        let publisher = GitPublisher::clone(origin.to_str().unwrap(), "")
+            .await
+            .unwrap();
+        let changes = Changes {
+            write: vec![
+                ("_posts/post.md".to_string(), "New".to_string()),
+                ("_data/trips/tour.yml".to_string(), "posts: []".to_string()),
+            ],
+            delete: vec!["assets/geo/post.geojson".to_string()],
+        };
+        let sha = publisher.publish(&changes, "Update post").await.unwrap();
+
+        let origin = Repository::open_bare(&origin).unwrap();
+        let head = origin
+            .find_reference("refs/heads/main")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        assert_eq!(head.id().to_string(), sha);
+        assert_eq!(head.message(), Some("Update post"));
+        assert_eq!(
+            origin_file(dir.path(), "_posts/post.md").as_deref(),
+            Some("New")
+        );
+        assert_eq!(
+            origin_file(dir.path(), "_data/trips/tour.yml").as_deref(),
+            Some("posts: []")
+        );
+        assert_eq!(origin_file(dir.path(), "assets/geo/post.geojson"), None);
+    }
+
+    #[tokio::test]
+    async fn test_commit_and_push_refuses_untracked_deletes() {
+        let dir = TempDir::new().unwrap();
+        init_origin(dir.path());
+        let repo = clone(dir.path(), "ours");
+
+        let changes = Changes {
+            write: vec![("_posts/new.md".to_string(), "New".to_string())],
+            delete: vec!["_posts/missing.md".to_string()],
+        };
+        assert_eq!(
+            commit_and_push(repo, "", &changes, "Delete").await,
+            Err("File to delete is not tracked: _posts/missing.md".to_string())
+        );
+    }
 }
@@ -0,0 +1,551 @@
+use crate::publisher::{Changes, File, Publisher};
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+const REPOSITORY: &str = "Kyrremann/plog";
+const BRANCH: &str = "main";
+
+#[derive(Deserialize)]
+struct Object {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct Reference {
+    object: Object,
+}
+
+#[derive(Deserialize)]
+struct TreeItem {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    tree: Vec<TreeItem>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    tree: Object,
+}
+
+#[derive(Serialize)]
+struct NewBlob<'a> {
+    content: &'a str,
+    encoding: &'a str,
+}
+
+#[derive(Serialize)]
+struct TreeEntry<'a> {
+    path: &'a str,
+    mode: &'a str,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    // A null SHA deletes the path from the base tree
+    sha: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct NewTree<'a> {
+    base_tree: &'a str,
+    tree: Vec<TreeEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct Author<'a> {
+    name: &'a str,
+    email: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewCommit<'a> {
+    message: &'a str,
+    tree: &'a str,
+    parents: Vec<&'a str>,
+    author: Author<'a>,
+}
+
+#[derive(Serialize)]
+struct UpdateReference<'a> {
+    sha: &'a str,
+    force: bool,
+}
+
+/// Publishes posts through the GitHub Git Data API, without cloning the repository.
+pub struct GitHubPublisher {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+}
+
+impl GitHubPublisher {
+    pub fn new(api_url: String, token: &str) -> Self {
+        GitHubPublisher {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(
+                method,
+                format!("{}/repos/{REPOSITORY}/{path}", self.api_url),
+            )
+            .bearer_auth(&self.token)
+            .header("User-Agent", "plogtion")
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+        action: &str,
+    ) -> Result<T, String> {
+        let response = request
+            .header("Accept", "application/vnd.github+json")
+            .send()
+            .await
+            .map_err(|err| format!("Failed to {action}: {err}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to {action}: {status}: {body}"));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse response to {action}: {err}"))
+    }
+}
+
+// Percent-encodes each segment, so names with spaces, `#`, `?` or `%` stay part of the path
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            segment
+                .bytes()
+                .map(|byte| match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                        (byte as char).to_string()
+                    }
+                    _ => format!("%{byte:02X}"),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[async_trait]
+impl Publisher for GitHubPublisher {
+    async fn read_file(&self, file_path: &str) -> Result<Option<String>, String> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("contents/{}?ref={BRANCH}", encode_path(file_path)),
+            )
+            .header("Accept", "application/vnd.github.raw+json")
+            .send()
+            .await
+            .map_err(|err| format!("Failed to read {file_path}: {err}"))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to read {file_path}: {status}: {body}"));
+        }
+
+        response
+            .text()
+            .await
+            .map(Some)
+            .map_err(|err| format!("Failed to read {file_path}: {err}"))
+    }
+
+    // One request for the whole tree, the contents API stops listing at 1000 files
+    async fn list_files(&self, dir: &str) -> Result<Vec<File>, String> {
+        let tree: Tree = self
+            .send(
+                self.request(
+                    reqwest::Method::GET,
+                    &format!("git/trees/{BRANCH}?recursive=1"),
+                ),
+                &format!("list {dir}"),
+            )
+            .await?;
+        if tree.truncated {
+            warn!("The tree of {BRANCH} is truncated, some files in {dir} are missing");
+        }
+
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+        let mut files: Vec<File> = tree
+            .tree
+            .into_iter()
+            .filter(|item| item.kind == "blob")
+            .filter(|item| {
+                item.path
+                    .strip_prefix(&prefix)
+                    .is_some_and(|name| !name.contains('/'))
+            })
+            .map(|item| File {
+                path: item.path,
+                id: item.sha,
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    async fn publish(&self, changes: &Changes, message: &str) -> Result<String, String> {
+        let head: Reference = self
+            .send(
+                self.request(reqwest::Method::GET, &format!("git/ref/heads/{BRANCH}")),
+                "get branch reference",
+            )
+            .await?;
+        let parent: Commit = self
+            .send(
+                self.request(
+                    reqwest::Method::GET,
+                    &format!("git/commits/{}", head.object.sha),
+                ),
+                "get head commit",
+            )
+            .await?;
+
+        let mut blobs = vec![];
+        for (_, content) in &changes.write {
+            let blob: Object = self
+                .send(
+                    self.request(reqwest::Method::POST, "git/blobs")
+                        .json(&NewBlob {
+                            content,
+                            encoding: "utf-8",
+                        }),
+                    "create blob",
+                )
+                .await?;
+            blobs.push(blob);
+        }
+        let tree: Object = self
+            .send(
+                self.request(reqwest::Method::POST, "git/trees")
+                    .json(&NewTree {
+                        base_tree: &parent.tree.sha,
+                        tree: changes
+                            .write
+                            .iter()
+                            .zip(&blobs)
+                            .map(|((path, _), blob)| TreeEntry {
+                                path,
+                                mode: "100644",
+                                kind: "blob",
+                                sha: Some(&blob.sha),
+                            })
+                            .chain(changes.delete.iter().map(|path| TreeEntry {
+                                path,
+                                mode: "100644",
+                                kind: "blob",
+                                sha: None,
+                            }))
+                            .collect(),
+                    }),
+                "create tree",
+            )
+            .await?;
+        let commit: Object = self
+            .send(
+                self.request(reqwest::Method::POST, "git/commits")
+                    .json(&NewCommit {
+                        message,
+                        tree: &tree.sha,
+                        parents: vec![&head.object.sha],
+                        author: Author {
+                            name: "Plog Bot",
+                            email: "plog-scaleway[bot]@users.noreply.github.com",
+                        },
+                    }),
+                "create commit",
+            )
+            .await?;
+
+        let _: Reference = self
+            .send(
+                self.request(reqwest::Method::PATCH, &format!("git/refs/heads/{BRANCH}"))
+                    .json(&UpdateReference {
+                        sha: &commit.sha,
+                        force: false,
+                    }),
+                "update branch reference",
+            )
+            .await?;
+
+        info!("Commit {} published through the GitHub API", commit.sha);
+        Ok(commit.sha)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use axum::extract::{Path, Query, State};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::{get, patch, post};
+    use axum::{Json, Router};
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    type Requests = Arc<Mutex<Vec<(String, Value)>>>;
+
+    async fn mock_github() -> (String, Requests) {
+        let requests = Requests::default();
+
+        fn record(requests: &Requests, endpoint: &str, body: Value) {
+            requests.lock().unwrap().push((endpoint.to_string(), body));
+        }
+
+        let app = Router::new()
+            .route(
+                "/repos/Kyrremann/plog/contents/{*path}",
+                get(|Path(path): Path<String>| async move {
+                    match path.as_str() {
+                        "_plogtion/post.md.tera" => Ok("{{ form.title }}".into_response()),
+                        "_posts/2023-10-01-100% day #1?.md" => Ok("---\n---\n".into_response()),
+                        _ => Err(StatusCode::NOT_FOUND),
+                    }
+                }),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/trees/main",
+                get(|Query(query): Query<HashMap<String, String>>| async move {
+                    assert_eq!(query.get("recursive").map(String::as_str), Some("1"));
+                    Json(json!({
+                        "sha": "main-tree",
+                        "tree": [
+                            { "type": "tree", "path": "_posts", "sha": "posts" },
+                            { "type": "blob", "path": "_posts/2023-10-02-b.md", "sha": "b" },
+                            { "type": "tree", "path": "_posts/drafts", "sha": "drafts" },
+                            { "type": "blob", "path": "_posts/drafts/c.md", "sha": "c" },
+                            { "type": "blob", "path": "_posts/2023-10-01-a.md", "sha": "a" },
+                            { "type": "blob", "path": "_config.yml", "sha": "config" },
+                        ],
+                        "truncated": false,
+                    }))
+                }),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/ref/heads/main",
+                get(|| async { Json(json!({ "object": { "sha": "head" } })) }),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/commits/{sha}",
+                get(|Path(sha): Path<String>| async move {
+                    Json(json!({ "sha": sha, "tree": { "sha": "base-tree" } }))
+                }),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/blobs",
+                post(
+                    |State(requests): State<Requests>, Json(body): Json<Value>| async move {
+                        record(&requests, "blobs", body);
+                        Json(json!({ "sha": "blob" }))
+                    },
+                ),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/trees",
+                post(
+                    |State(requests): State<Requests>, Json(body): Json<Value>| async move {
+                        record(&requests, "trees", body);
+                        Json(json!({ "sha": "tree" }))
+                    },
+                ),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/commits",
+                post(
+                    |State(requests): State<Requests>, Json(body): Json<Value>| async move {
+                        record(&requests, "commits", body);
+                        Json(json!({ "sha": "commit" }))
+                    },
+                ),
+            )
+            .route(
+                "/repos/Kyrremann/plog/git/refs/heads/main",
+                patch(
+                    |State(requests): State<Requests>, Json(body): Json<Value>| async move {
+                        record(&requests, "refs", body);
+                        Json(json!({ "object": { "sha": "commit" } }))
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+
+        let url = test_server::serve(app).await;
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_publish_creates_blob_tree_commit_and_updates_ref() {
+        let (url, requests) = mock_github().await;
+        let publisher = GitHubPublisher::new(url, "secret");
+
+        let changes = Changes {
+            write: vec![
+                (
+                    "_posts/2023-10-01-test-post.md".to_string(),
+                    "Hello".to_string(),
+                ),
+                ("_data/trips/tour.yml".to_string(), "posts: []".to_string()),
+            ],
+            delete: vec!["assets/geo/2023-10-01-test-post.geojson".to_string()],
+        };
+        assert_eq!(
+            publisher.publish(&changes, "Test Post").await,
+            Ok("commit".to_string())
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            *requests,
+            vec![
+                (
+                    "blobs".to_string(),
+                    json!({ "content": "Hello", "encoding": "utf-8" })
+                ),
+                (
+                    "blobs".to_string(),
+                    json!({ "content": "posts: []", "encoding": "utf-8" })
+                ),
+                (
+                    "trees".to_string(),
+                    json!({
+                        "base_tree": "base-tree",
+                        "tree": [
+                            {
+                                "path": "_posts/2023-10-01-test-post.md",
+                                "mode": "100644",
+                                "type": "blob",
+                                "sha": "blob",
+                            },
+                            {
+                                "path": "_data/trips/tour.yml",
+                                "mode": "100644",
+                                "type": "blob",
+                                "sha": "blob",
+                            },
+                            {
+                                "path": "assets/geo/2023-10-01-test-post.geojson",
+                                "mode": "100644",
+                                "type": "blob",
+                                "sha": null,
+                            },
+                        ],
+                    })
+                ),
+                (
+                    "commits".to_string(),
+                    json!({
+                        "message": "Test Post",
+                        "tree": "tree",
+                        "parents": ["head"],
+                        "author": {
+                            "name": "Plog Bot",
+                            "email": "plog-scaleway[bot]@users.noreply.github.com",
+                        },
+                    })
+                ),
+                (
+                    "refs".to_string(),
+                    json!({ "sha": "commit", "force": false })
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_file() {
+        let (url, _) = mock_github().await;
+        let publisher = GitHubPublisher::new(url, "secret");
+
+        assert_eq!(
+            publisher.read_file("_plogtion/post.md.tera").await,
+            Ok(Some("{{ form.title }}".to_string()))
+        );
+        assert_eq!(publisher.read_file("_plogtion/missing").await, Ok(None));
+        assert_eq!(
+            publisher
+                .read_file("_posts/2023-10-01-100% day #1?.md")
+                .await,
+            Ok(Some("---\n---\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(
+            encode_path("_posts/2023-10-01-100% day #1?.md"),
+            "_posts/2023-10-01-100%25%20day%20%231%3F.md"
+        );
+        assert_eq!(
+            encode_path("_posts/tur-til-bødø.md"),
+            "_posts/tur-til-b%C3%B8d%C3%B8.md"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_files() {
+        let (url, _) = mock_github().await;
+        let publisher = GitHubPublisher::new(url, "secret");
+
+        assert_eq!(
+            publisher.list_files("_posts").await,
+            Ok(vec![
+                File {
+                    path: "_posts/2023-10-01-a.md".to_string(),
+                    id: "a".to_string(),
+                },
+                File {
+                    path: "_posts/2023-10-02-b.md".to_string(),
+                    id: "b".to_string(),
+                },
+            ])
+        );
+        assert_eq!(publisher.list_files("_data").await, Ok(vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_publish_reports_api_errors() {
+        let publisher = GitHubPublisher::new("http://127.0.0.1:1".to_string(), "secret");
+
+        let changes = Changes {
+            write: vec![("_posts/a.md".to_string(), "Hello".to_string())],
+            ..Default::default()
+        };
+        let result = publisher.publish(&changes, "Test").await;
+
+        assert!(
+            result
+                .unwrap_err()
+                .starts_with("Failed to get branch reference")
+        );
+    }
+}
@@ -1,66 +1,70 @@
 mod brevo;
+mod categories;
+mod dates;
+mod geo;
+mod geocoding;
 mod git;
+mod github;
+mod live;
+mod markdown;
+mod mastodon;
+mod meta;
+mod notifier;
+mod posts;
+mod publisher;
+mod site;
+mod slug;
+mod strava;
+mod taxonomy;
 mod tera;
+#[cfg(test)]
+mod test_server;
+mod trips;
 
-use crate::tera::UploadForm;
-use axum::extract::Multipart;
-use axum::http::StatusCode;
-use axum::response::Html;
-use chrono::{Datelike, NaiveDate};
-use log::{error, info};
+use crate::categories::Categories;
+use crate::dates::PostDate;
+pub use crate::geocoding::Geocoding;
+use crate::notifier::Notify;
+use crate::tera::{ImageMetadata, UploadForm};
+use axum::Json;
+use axum::body::Body;
+use axum::extract::{FromRequest, Multipart};
+use axum::http::{HeaderMap, Method, Request, StatusCode, header};
+use axum::response::{Html, IntoResponse, Response};
+use chrono::NaiveDate;
+use log::{error, info, warn};
 use serde::Deserialize;
+use std::time::Duration;
 
 const DEFAULT_IMAGE_URL: &str = "https://kyrremann-plog.s3.nl-ams.scw.cloud";
+const ALLOWED_ORIGINS: [&str; 2] = ["http://localhost:4000", "https://kyrremann.no"];
+const TAXONOMY_MAX_AGE: Duration = Duration::from_secs(10 * 60);
 
-#[derive(Deserialize)]
-pub struct Geocoding {
-    #[serde(default)]
-    pub suburb: String,
-    #[serde(default)]
-    pub town: String,
-    #[serde(default)]
-    pub city: String,
-    #[serde(default)]
-    pub municipality: String,
-    #[serde(default)]
-    pub province: String,
-    pub country: String,
-}
-
+/// An image location. The form sends the address when it has one, otherwise it is looked up.
 #[derive(Deserialize)]
 pub struct Location {
-    pub geocoding: Geocoding,
+    #[serde(default)]
+    pub geocoding: Option<Geocoding>,
     pub latitude: f64,
     pub longitude: f64,
 }
 
-impl Location {
-    fn geocoding_as_string(&self) -> String {
-        let mut parts = vec![];
-        if !self.geocoding.suburb.is_empty() {
-            parts.push(self.geocoding.suburb.clone());
-        }
-        if !self.geocoding.town.is_empty() {
-            parts.push(self.geocoding.town.clone());
-        }
-        if !self.geocoding.city.is_empty() {
-            parts.push(self.geocoding.city.clone());
+/// The function's entry point: `POST` publishes a post, `GET` lists the taxonomy.
+pub async fn handle(request: Request<Body>) -> Response {
+    match *request.method() {
+        Method::POST => match Multipart::from_request(request, &()).await {
+            Ok(multipart) => handle_post(multipart).await.into_response(),
+            Err(rejection) => rejection.into_response(),
+        },
+        Method::GET => {
+            let headers = request.headers().clone();
+            handle_taxonomy(headers).await.into_response()
         }
-        if !self.geocoding.municipality.is_empty() {
-            parts.push(self.geocoding.municipality.clone());
-        }
-        if !self.geocoding.province.is_empty() {
-            parts.push(self.geocoding.province.clone());
-        }
-        if !self.geocoding.country.is_empty() {
-            parts.push(self.geocoding.country.clone());
-        }
-
-        parts.join(", ")
+        _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
     }
 }
 
-pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCode, String)> {
+pub async fn handle_post(mut multipart: Multipart) -> Result<Html<String>, (StatusCode, String)> {
     env_logger::try_init().unwrap_or_else(|_| {
         eprintln!("Failed to initialize logger, using default settings");
     });
@@ -70,6 +74,17 @@ pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCod
         ..Default::default()
     };
     let mut token = String::new();
+    let mut addresses: Vec<(String, Option<Geocoding>)> = vec![];
+    let mut notify = Notify::default();
+    let mut notify_lists = None;
+    let meta_fields = meta::AllowList::from_env();
+    let timezone = dates::timezone_from_env().map_err(|err| {
+        error!("Invalid TIMEZONE: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid TIMEZONE: {err}"),
+        )
+    })?;
 
     while let Some(field) = multipart.next_field().await.map_err(|err| {
         error!("Failed to read multipart field: {err}");
@@ -86,13 +101,78 @@ pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCod
         match name.as_str() {
             "token" => token = value,
             "title" => form.title = value.trim().to_string(),
-            "strava" => form.strava = value,
-            "date" => form.date = value,
-            "categories" => form.categories = value,
+            "slug" => form.slug = value.trim().to_string(),
+            "strava" if !value.trim().is_empty() => {
+                let id = strava::parse_activity_id(&value).map_err(|err| {
+                    error!("Invalid Strava activity: {err}");
+                    (StatusCode::BAD_REQUEST, err)
+                })?;
+                form.strava = id.to_string();
+            }
+            "strava" => {}
+            "allow_duplicate" => {
+                form.allow_duplicate = matches!(value.as_str(), "true" | "on" | "1")
+            }
+            "date" if !value.trim().is_empty() => {
+                let date = PostDate::parse(&value, timezone).map_err(|err| {
+                    error!("Invalid date: {err}");
+                    (StatusCode::BAD_REQUEST, err)
+                })?;
+                form.date = Some(date);
+            }
+            "end_date" if !value.trim().is_empty() => {
+                let end_date =
+                    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").map_err(|err| {
+                        error!("Invalid end date {value}: {err}");
+                        (
+                            StatusCode::BAD_REQUEST,
+                            format!("Invalid end date {value}, use YYYY-MM-DD"),
+                        )
+                    })?;
+                form.end_date = Some(end_date);
+            }
+            "date" | "end_date" => {}
+            "notify" => {
+                notify = Notify::parse(&value, timezone).map_err(|err| {
+                    error!("Invalid notify: {err}");
+                    (StatusCode::BAD_REQUEST, err)
+                })?;
+            }
+            "notify_lists" if !value.trim().is_empty() => {
+                let list_ids = brevo::parse_list_ids(&value).map_err(|err| {
+                    error!("Invalid notify lists: {err}");
+                    (StatusCode::BAD_REQUEST, err)
+                })?;
+                notify_lists = Some(list_ids);
+            }
+            "notify_lists" => {}
+            "trip" => form.trip = value.trim().to_string(),
+            "day" if !value.trim().is_empty() => {
+                let day = value.trim().parse().map_err(|err| {
+                    error!("Invalid day {value}: {err}");
+                    (StatusCode::BAD_REQUEST, format!("Invalid day: {value}"))
+                })?;
+                form.day = Some(day);
+            }
+            "day" => {}
+            "intro" => form.intro = value.trim().replace("\r\n", "\n"),
+            "outro" => form.outro = value.trim().replace("\r\n", "\n"),
+            "categories" => form.categories = taxonomy::parse_terms(&value),
+            "tags" => form.tags = taxonomy::parse_terms(&value),
             "feature_image" => {
                 let file_name = value;
                 form.feature.file_name = file_name.clone();
             }
+            name if name.starts_with(meta::PREFIX) => {
+                let key = name.strip_prefix(meta::PREFIX).unwrap_or_default();
+                meta_fields.check(key).map_err(|err| {
+                    error!("Invalid custom field: {err}");
+                    (StatusCode::BAD_REQUEST, err)
+                })?;
+                if !value.trim().is_empty() {
+                    form.meta.insert(key.to_string(), meta::parse_value(&value));
+                }
+            }
             name if name.ends_with("_alt_text") => {
                 let text = value.trim();
                 let key = name.strip_suffix("_alt_text").unwrap_or_default();
@@ -109,19 +189,22 @@ pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCod
                 form.images.entry(key.to_string()).or_default().description =
                     text.replace("\r\n", "\n");
             }
-            name if name.ends_with("_location") => {
-                let text = value;
+            name if name.ends_with("_location") && !value.trim().is_empty() => {
                 let key = name.strip_suffix("_location").unwrap_or_default();
-                match serde_json::from_str::<Location>(&text) {
-                    Ok(location) => {
-                        let metadata = form.images.entry(key.to_string()).or_default();
-                        metadata.location = location.geocoding_as_string();
-                        metadata.coordinates =
-                            format!("{},{}", location.latitude, location.longitude);
-                    }
-                    Err(err) => error!("Failed to parse location JSON: {err}"),
-                }
+                let location = serde_json::from_str::<Location>(&value).map_err(|err| {
+                    error!("Failed to parse location JSON: {err}");
+                    (
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid location for {key}: {err}"),
+                    )
+                })?;
+                addresses.push((key.to_string(), location.geocoding));
+                let metadata = form.images.entry(key.to_string()).or_default();
+                metadata.coordinates = format!("{},{}", location.latitude, location.longitude);
+                metadata.latitude = Some(location.latitude);
+                metadata.longitude = Some(location.longitude);
             }
+            name if name.ends_with("_location") => {}
             "filepond" => {
                 let path = value;
                 let file_name = path.split('/').next_back().unwrap_or_default().to_string();
@@ -130,8 +213,11 @@ pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCod
                 im.file_name = file_name.clone();
                 im.image_url = format!("{DEFAULT_IMAGE_URL}/{path}");
             }
+            // Anything else must be declared by one of the post's categories, see UploadForm::validate
             _ => {
-                return Err((StatusCode::BAD_REQUEST, format!("Unexpected field: {name}")));
+                if !value.trim().is_empty() {
+                    form.fields.insert(name, meta::parse_value(&value));
+                }
             }
         }
     }
@@ -147,29 +233,97 @@ pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCod
         )
     })?;
 
-    let repository = git::clone_repository(&github_token).await.map_err(|err| {
-        error!("Failed to clone repository: {err}");
+    let publisher = publisher::from_env(&github_token).await.map_err(|err| {
+        error!("Failed to set up publisher: {err}");
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to clone repository".to_string(),
+            "Failed to set up publisher".to_string(),
         )
     })?;
 
-    if let Some(image) = form.images.get(&form.feature.file_name) {
-        form.feature.image_url = image.image_url.clone();
-        form.feature.description = image.description.clone(); // For the email campaign
-    } else {
-        info!("No featured image specified, selecting the first available image");
-        let mut keys: Vec<_> = form.images.keys().cloned().collect();
-        keys.sort_by_key(|k| k.to_lowercase());
-        let featured_image_key = keys.first().cloned().unwrap_or_default();
+    let location_format = geocoding::LocationFormat::from_env().map_err(|err| {
+        error!("Invalid LOCATION_FORMAT: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid LOCATION_FORMAT: {err}"),
+        )
+    })?;
+    let map_provider = geo::MapProvider::from_env().map_err(|err| {
+        error!("Invalid MAP_PROVIDER: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid MAP_PROVIDER: {err}"),
+        )
+    })?;
+    let slugger = slug::Slugger::from_env().map_err(|err| {
+        error!("Invalid slug settings: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid slug settings: {err}"),
+        )
+    })?;
+    let notifiers = notifier::from_env().map_err(|err| {
+        error!("Invalid notifier settings: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid notifier settings: {err}"),
+        )
+    })?;
+    let live_check = live::LiveCheck::from_env().map_err(|err| {
+        error!("Invalid live check settings: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid live check settings: {err}"),
+        )
+    })?;
+
+    let categories = match publisher.read_file(categories::CATEGORIES_PATH).await {
+        Ok(Some(config)) => Categories::parse(&config),
+        Ok(None) => Ok(Categories::default()),
+        Err(err) => Err(err),
+    }
+    .map_err(|err| {
+        error!("Failed to load categories: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to load categories: {err}"),
+        )
+    })?;
+
+    let site = publisher
+        .read_file(site::CONFIG_PATH)
+        .await
+        .and_then(|config| site::SiteConfig::parse(config.as_deref()))
+        .map(site::SiteConfig::with_env_overrides)
+        .and_then(|site| {
+            // Subscribers click this link in the newsletter, so it can't be relative
+            if site.url.is_empty() {
+                Err("url is not set in _config.yml or SITE_URL".to_string())
+            } else {
+                Ok(site)
+            }
+        })
+        .map_err(|err| {
+            error!("Failed to load site config: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to load site config: {err}"),
+            )
+        })?;
+
+    form.feature = feature_image(&form);
 
-        let image = form.images.get(&featured_image_key).unwrap();
-        form.feature = image.clone();
-        form.feature.file_name = featured_image_key.clone();
+    if let Notify::At(time) = notify
+        && time <= chrono::Utc::now()
+    {
+        error!("Notify time {time} has passed");
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Notify time {time} has passed"),
+        ));
     }
 
-    if let Err(err) = form.validate() {
+    if let Err(err) = form.validate(&categories) {
         // at this point we're safe to say form isn't malformed, right?
         let serialized = serde_json::to_string(&form).unwrap();
         error!("Form validation failed: {err}");
@@ -180,59 +334,477 @@ pub async fn handle(mut multipart: Multipart) -> Result<Html<String>, (StatusCod
     }
 
     info!(
-        "Title: {}, Categories: {}, Strava: {}, Date: {}, Feature: {:?}, Images: {:?}",
-        form.title, form.categories, form.strava, form.date, form.feature, form.images,
+        "Title: {}, Categories: {:?}, Tags: {:?}, Strava: {}, Date: {:?}, Feature: {:?}, Images: {:?}",
+        form.title, form.categories, form.tags, form.strava, form.date, form.feature, form.images,
     );
 
-    let safe_file_name = tera::create_post(&form).map_err(|err| {
-        error!("Failed to create post: {err}");
+    let existing_posts = posts::scan(&*publisher).await.map_err(|err| {
+        error!("Failed to scan existing posts: {err}");
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create post: {err}"),
+            "Failed to scan existing posts".to_string(),
         )
     })?;
 
-    let date = NaiveDate::parse_from_str(&form.date, "%Y-%m-%d").map_err(|err| {
-        error!("Failed to parse date {}: {}", form.date, err);
-        (StatusCode::BAD_REQUEST, "Invalid date format".to_string())
-    })?;
-
-    let file_in_git_dir = format!("_posts/{}-{}.md", form.date, safe_file_name);
+    // Validated above, and the file name, URL and front matter all use this one value
+    let date = form
+        .date
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Date cannot be empty".to_string()))?;
+    let slug = slugger.slugify(if form.slug.is_empty() {
+        &form.title
+    } else {
+        &form.slug
+    });
+    if slug.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Slug cannot be empty, use letters or digits in the title or slug".to_string(),
+        ));
+    }
+    let path = format!("{}/{}-{slug}.md", posts::POSTS_DIR, date.day());
+    let url = site.post_url(date, &slug, &form.categories);
+    let published = published_post(&existing_posts, &site, &form, &path, &url)?;
+    form.slug = slug;
 
-    git::commit_and_push(repository, &github_token, &file_in_git_dir, &form.title)
-        .await
-        .map_err(|err| {
-            error!("Failed to commit and push: {err}");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to commit and push".to_string(),
-            )
-        })?;
+    if published.is_none()
+        && let Ok(id) = form.strava.parse()
+        && !form.allow_duplicate
+        && let Some(existing) = posts::find_by_strava(&existing_posts, id)
+    {
+        let link = existing.url(&site).unwrap_or_else(|| existing.path.clone());
+        error!("Strava activity {id} is already used by {}", existing.path);
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "A post for Strava activity {id} already exists: {link}\nSet allow_duplicate to publish anyway"
+            ),
+        ));
+    }
 
-    let post_url = format!(
-        "https://kyrremann.no/plog/{}/{:02}/{}",
-        date.year(),
-        date.month(),
-        safe_file_name
+    let mut warnings = taxonomy::near_duplicates(
+        "Category",
+        &form.categories,
+        &taxonomy::existing_categories(&existing_posts),
     );
-    info!("Post URL: {post_url}");
-
-    brevo::post_campaign(
-        form.title.clone(),
-        form.feature.description.clone(),
-        form.feature.image_url.clone(),
-        post_url.clone(),
-    )
-    .await
-    .map_err(|err| {
-        error!("Failed to post campaign: {err}");
+    warnings.extend(taxonomy::near_duplicates(
+        "Tag",
+        &form.tags,
+        &taxonomy::existing_tags(&existing_posts),
+    ));
+
+    if let (Ok(id), Some(client)) = (form.strava.parse(), strava::from_env()) {
+        match client.activity(id).await {
+            Ok(activity) => form.strava_activity = Some(activity),
+            Err(err) => warnings.push(format!("Failed to fetch Strava activity {id}: {err}")),
+        }
+    }
+
+    let mut geocoder = None;
+    for (key, geocoding) in addresses {
+        let Some(metadata) = form.images.get_mut(&key) else {
+            continue;
+        };
+        let geocoding = match (geocoding, metadata.latitude, metadata.longitude) {
+            (Some(geocoding), _, _) if !geocoding.is_empty() => geocoding,
+            (_, Some(latitude), Some(longitude)) => {
+                let geocoder = geocoder.get_or_insert_with(geocoding::from_env);
+                match geocoder.reverse(latitude, longitude).await {
+                    Ok(geocoding) => geocoding,
+                    Err(err) => {
+                        warnings.push(format!("Failed to find location of {key}: {err}"));
+                        continue;
+                    }
+                }
+            }
+            _ => continue,
+        };
+        metadata.location = geocoding.format(&location_format);
+        if form.feature.file_name == key {
+            form.feature.location = metadata.location.clone();
+        }
+    }
+
+    for warning in &warnings {
+        warn!("{warning}");
+    }
+
+    let trip = if form.trip.is_empty() {
+        None
+    } else {
+        let path = trips::trip_path(&form.trip);
+        let trip = publisher
+            .read_file(&path)
+            .await
+            .and_then(|yaml| trips::Trip::parse(yaml.as_deref()))
+            .map_err(|err| {
+                error!("Failed to read trip {path}: {err}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to read trip {path}: {err}"),
+                )
+            })?;
+        form.day = Some(form.day.unwrap_or_else(|| trip.next_day()));
+        Some((path, trip))
+    };
+
+    let template_path = match categories.template(&form.categories) {
+        Some((_, template)) => format!("_plogtion/{template}"),
+        None => tera::TEMPLATE_PATH.to_string(),
+    };
+    let template = publisher.read_file(&template_path).await.map_err(|err| {
+        error!("Failed to read post template: {err}");
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to post campaign".to_string(),
+            "Failed to read post template".to_string(),
         )
     })?;
+    match (&template, categories.template(&form.categories)) {
+        (Some(_), _) => info!("Using post template from {template_path}"),
+        (None, Some((category, _))) => {
+            error!("Template {template_path} for category {category} does not exist");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Template {template_path} for category {category} does not exist"),
+            ));
+        }
+        (None, None) => {}
+    }
+
+    // A resubmitted form is not published again, only announced where that didn't happen yet
+    let post_url = match published {
+        Some(existing) => {
+            warnings.push(format!(
+                "{} is already published, so it was not committed again",
+                existing.path
+            ));
+            existing.url(&site).unwrap_or(url)
+        }
+        None => {
+            let content =
+                tera::render_post(&form, template.as_deref(), map_provider).map_err(|err| {
+                    error!("Failed to render post: {err}");
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to render post: {err}"),
+                    )
+                })?;
+
+            info!("Post URL: {url}");
+
+            let mut files = vec![];
+            if let (Some((trip_path, mut trip)), Some(day)) = (trip, form.day) {
+                trip.add_post(trips::TripPost {
+                    day,
+                    title: form.title.clone(),
+                    date: date.to_string(),
+                    path: path.clone(),
+                    url: url.clone(),
+                });
+                let yaml = trip.to_yaml().map_err(|err| {
+                    error!("Failed to update trip {trip_path}: {err}");
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to update trip {trip_path}"),
+                    )
+                })?;
+                files.push((trip_path, yaml));
+            }
+            if let Some(collection) = geo::feature_collection(&form.images) {
+                files.push((
+                    geo::geojson_path(date.day(), &form.slug),
+                    serde_json::to_string_pretty(&collection).unwrap(),
+                ));
+            }
+            files.insert(0, (path, content));
+
+            let changes = publisher::Changes {
+                write: files,
+                ..Default::default()
+            };
+            let sha = publisher
+                .publish(&changes, &form.title)
+                .await
+                .map_err(|err| {
+                    error!("Failed to publish post: {err}");
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to publish post".to_string(),
+                    )
+                })?;
+            info!("Published {url} in commit {sha}");
+            url
+        }
+    };
+
+    let announcement = announcement(&form, &post_url, notify_lists, notify);
+    // Readers should not get a link to a page that is still building
+    let live = match (&announcement.when, &live_check) {
+        (Notify::Never, _) | (_, None) => Ok(false),
+        // The site is built long before a later send time, and waiting for it would only risk a timeout
+        (Notify::At(time), Some(check)) if sent_after(*time, check.timeout()) => Ok(false),
+        (_, Some(check)) => check.wait(&post_url).await.map(|_| true),
+    };
+    match live {
+        Ok(_) if announcement.when == Notify::Never => {
+            info!("Not announcing {post_url}")
+        }
+        Ok(live) => {
+            let announcement = notifier::Announcement {
+                live,
+                ..announcement
+            };
+            // The post is published either way, so a failed announcement is only a warning
+            for notifier in &notifiers {
+                match notifier.notify(&announcement).await {
+                    Ok(notifier::Delivery::Sent) => {}
+                    Ok(notifier::Delivery::Skipped(reason)) => {
+                        warnings.push(format!("{} not sent: {reason}", notifier.name()))
+                    }
+                    Err(err) => {
+                        error!("Failed to notify {}: {err}", notifier.name());
+                        warnings.push(format!("{} failed: {err}", notifier.name()));
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            warn!("Skipped the announcements: {err}");
+            warnings.push(format!("Nothing announced: {err}"));
+        }
+    }
+
+    taxonomy::invalidate_cache();
 
-    Ok(Html(
-        "Form and multipart data processed successfully!".to_string(),
+    let mut message = "Form and multipart data processed successfully!".to_string();
+    for warning in warnings {
+        message.push_str(&format!("\nWarning: {warning}"));
+    }
+    Ok(Html(message))
+}
+
+/// The image chosen by `feature_image`, or the first one by file name, with all its metadata.
+fn feature_image(form: &UploadForm) -> ImageMetadata {
+    let file_name = if form.images.contains_key(&form.feature.file_name) {
+        form.feature.file_name.clone()
+    } else {
+        info!("No featured image specified, selecting the first available image");
+        let mut keys: Vec<_> = form.images.keys().cloned().collect();
+        keys.sort_by_key(|k| k.to_lowercase());
+        keys.first().cloned().unwrap_or_default()
+    };
+    ImageMetadata {
+        file_name: file_name.clone(),
+        ..form.images.get(&file_name).cloned().unwrap_or_default()
+    }
+}
+
+fn announcement(
+    form: &UploadForm,
+    post_url: &str,
+    list_ids: Option<Vec<i64>>,
+    when: Notify,
+) -> notifier::Announcement {
+    // The intro introduces the whole post, the feature image description only its image.
+    // Both are Markdown, which newsletters and statuses would show as typed.
+    let description = markdown::plain_text(if form.intro.is_empty() {
+        &form.feature.description
+    } else {
+        &form.intro
+    });
+    notifier::Announcement {
+        title: form.title.clone(),
+        description,
+        image_url: form.feature.image_url.clone(),
+        image_alt_text: form.feature.alt_text.clone(),
+        post_url: post_url.to_string(),
+        list_ids,
+        when,
+        live: false,
+    }
+}
+
+/// Whether an announcement at `time` goes out only after `timeout` from now.
+fn sent_after(time: chrono::DateTime<chrono::Utc>, timeout: Duration) -> bool {
+    (time - chrono::Utc::now())
+        .to_std()
+        .is_ok_and(|wait| wait > timeout)
+}
+
+/// The post a resubmitted form already published at `path` or `url`, refusing any other post there.
+fn published_post<'a>(
+    existing_posts: &'a [posts::ExistingPost],
+    site: &site::SiteConfig,
+    form: &UploadForm,
+    path: &str,
+    url: &str,
+) -> Result<Option<&'a posts::ExistingPost>, (StatusCode, String)> {
+    let Some(existing) = posts::find_by_path_or_url(existing_posts, site, path, url) else {
+        return Ok(None);
+    };
+    let same_activity = form
+        .strava
+        .parse()
+        .is_ok_and(|id: u64| existing.front_matter.strava == Some(id));
+    if existing.front_matter.title == form.title || same_activity {
+        info!("{} is already published", existing.path);
+        return Ok(Some(existing));
+    }
+
+    error!("{path} clashes with {}", existing.path);
+    Err((
+        StatusCode::CONFLICT,
+        format!(
+            "A post already exists at {url}: {}\nChoose another title or slug",
+            existing.path
+        ),
     ))
 }
+
+/// Lists the categories and tags already used in the plog with their counts, for autocompletion.
+pub async fn handle_taxonomy(headers: HeaderMap) -> Result<Response, (StatusCode, String)> {
+    env_logger::try_init().unwrap_or_else(|_| {
+        eprintln!("Failed to initialize logger, using default settings");
+    });
+
+    let taxonomy = match taxonomy::cached(TAXONOMY_MAX_AGE) {
+        Some(taxonomy) => taxonomy,
+        None => {
+            let github_token = std::env::var("GITHUB_TOKEN").map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "GITHUB_TOKEN not set".to_string(),
+                )
+            })?;
+
+            let reader = publisher::reader_from_env(&github_token);
+            let existing_posts = posts::scan(&*reader).await.map_err(|err| {
+                error!("Failed to scan existing posts: {err}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to scan existing posts".to_string(),
+                )
+            })?;
+
+            let taxonomy = taxonomy::count(&existing_posts);
+            taxonomy::cache(&taxonomy);
+            taxonomy
+        }
+    };
+
+    let mut response = Json(taxonomy).into_response();
+    if let Some(origin) = headers
+        .get(header::ORIGIN)
+        .filter(|origin| ALLOWED_ORIGINS.contains(&origin.to_str().unwrap_or_default()))
+    {
+        response
+            .headers_mut()
+            .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::posts::{ExistingPost, PostFrontMatter};
+    use crate::site::SiteConfig;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_announcement_of_feature_image() {
+        let image = |description: &str, alt_text: &str| ImageMetadata {
+            description: description.to_string(),
+            alt_text: alt_text.to_string(),
+            image_url: format!("https://example.com/{alt_text}.jpg"),
+            ..Default::default()
+        };
+        let mut form = UploadForm {
+            title: "Test Post".to_string(),
+            images: std::collections::HashMap::from([
+                (
+                    "a.jpg".to_string(),
+                    image("A *ride*", "A bike by the fjord"),
+                ),
+                ("b.jpg".to_string(), image("A climb", "A steep road")),
+            ]),
+            ..Default::default()
+        };
+
+        form.feature = feature_image(&form);
+        let first = announcement(&form, "https://example.com/test-post", None, Notify::Never);
+        assert_eq!(form.feature.file_name, "a.jpg");
+        assert_eq!(first.description, "A ride");
+        // Mastodon uploads the alt text as the media description
+        assert_eq!(first.image_alt_text, "A bike by the fjord");
+
+        form.feature = ImageMetadata {
+            file_name: "b.jpg".to_string(),
+            ..Default::default()
+        };
+        form.feature = feature_image(&form);
+        let chosen = announcement(&form, "https://example.com/test-post", None, Notify::Never);
+        assert_eq!(form.feature.file_name, "b.jpg");
+        assert_eq!(chosen.image_url, "https://example.com/A steep road.jpg");
+        assert_eq!(chosen.image_alt_text, "A steep road");
+    }
+
+    #[test]
+    fn test_sent_after() {
+        let now = chrono::Utc::now();
+        let timeout = Duration::from_secs(60);
+        assert!(sent_after(now + chrono::Duration::days(1), timeout));
+        assert!(!sent_after(now + chrono::Duration::seconds(30), timeout));
+        assert!(!sent_after(now - chrono::Duration::seconds(30), timeout));
+    }
+
+    #[test]
+    fn test_published_post() {
+        let existing_posts = vec![ExistingPost {
+            path: "_posts/2023-10-01-first-day.md".to_string(),
+            front_matter: PostFrontMatter {
+                title: "First day".to_string(),
+                strava: Some(1),
+                ..Default::default()
+            },
+        }];
+        let site = SiteConfig {
+            url: "https://kyrremann.no".to_string(),
+            baseurl: "/plog".to_string(),
+            permalink: "/:year/:month/:title".to_string(),
+        };
+        let form = |title: &str, strava: &str| UploadForm {
+            title: title.to_string(),
+            strava: strava.to_string(),
+            ..Default::default()
+        };
+        // Another day of the same month, at the same URL
+        let path = "_posts/2023-10-15-first-day.md";
+        let url = "https://kyrremann.no/plog/2023/10/first-day";
+
+        assert_eq!(
+            published_post(&existing_posts, &site, &form("First day", ""), path, url),
+            Ok(existing_posts.first())
+        );
+        assert_eq!(
+            published_post(&existing_posts, &site, &form("First Day!", "1"), path, url),
+            Ok(existing_posts.first())
+        );
+        assert_eq!(
+            published_post(&existing_posts, &site, &form("First day?", "2"), path, url),
+            Err((
+                StatusCode::CONFLICT,
+                "A post already exists at https://kyrremann.no/plog/2023/10/first-day: _posts/2023-10-01-first-day.md\nChoose another title or slug".to_string()
+            ))
+        );
+        assert_eq!(
+            published_post(
+                &existing_posts,
+                &site,
+                &form("First day", "1"),
+                "_posts/2023-11-01-first-day.md",
+                "https://kyrremann.no/plog/2023/11/first-day"
+            ),
+            Ok(None)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use log::info;
+use std::time::{Duration, Instant};
+
+// Scaleway stops the function after 300 seconds, and publishing and announcing need some of them
+const MAX_TIMEOUT: Duration = Duration::from_secs(240);
+
+/// Polls a freshly published post until the site serves it.
+pub struct LiveCheck {
+    client: reqwest::Client,
+    timeout: Duration,
+    interval: Duration,
+}
+
+impl LiveCheck {
+    pub fn new(timeout: Duration, interval: Duration) -> Self {
+        LiveCheck {
+            client: reqwest::Client::new(),
+            timeout,
+            interval,
+        }
+    }
+
+    // LIVE_CHECK_TIMEOUT and LIVE_CHECK_INTERVAL are in seconds, a timeout of 0 skips the check.
+    // The timeout must leave the function time to answer.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let seconds = |name: &str, default: u64| -> Result<Duration, String> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .trim()
+                    .parse()
+                    .map(Duration::from_secs)
+                    .map_err(|_| format!("Invalid {name}: {value}")),
+                Err(_) => Ok(Duration::from_secs(default)),
+            }
+        };
+        let timeout = check_timeout(seconds("LIVE_CHECK_TIMEOUT", 60)?)?;
+        let interval = seconds("LIVE_CHECK_INTERVAL", 10)?;
+        Ok((!timeout.is_zero()).then(|| LiveCheck::new(timeout, interval)))
+    }
+
+    /// The longest [`LiveCheck::wait`] takes before giving up.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Waits until `url` answers 200 OK, returning how long it took.
+    pub async fn wait(&self, url: &str) -> Result<Duration, String> {
+        let started = Instant::now();
+        loop {
+            let outcome = match self.client.get(url).send().await {
+                Ok(response) if response.status() == reqwest::StatusCode::OK => {
+                    let elapsed = started.elapsed();
+                    info!("{url} is live after {}s", elapsed.as_secs());
+                    return Ok(elapsed);
+                }
+                Ok(response) => response.status().to_string(),
+                Err(err) => format!("Request failed: {err}"),
+            };
+
+            if started.elapsed() + self.interval > self.timeout {
+                return Err(format!(
+                    "{url} was not live after {}s, last answer: {outcome}",
+                    self.timeout.as_secs()
+                ));
+            }
+            info!("Waiting for {url}: {outcome}");
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+fn check_timeout(timeout: Duration) -> Result<Duration, String> {
+    if timeout > MAX_TIMEOUT {
+        return Err(format!(
+            "Invalid LIVE_CHECK_TIMEOUT: {}s would outlast the function, use at most {}s",
+            timeout.as_secs(),
+            MAX_TIMEOUT.as_secs()
+        ));
+    }
+    Ok(timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use axum::Router;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use pretty_assertions::assert_eq;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Answers 404 until the site has been "built" after `builds` requests
+    async fn mock_site(builds: usize) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/plog/2023/10/test-post",
+                get(move |State(requests): State<Arc<AtomicUsize>>| async move {
+                    if requests.fetch_add(1, Ordering::SeqCst) + 1 < builds {
+                        StatusCode::NOT_FOUND
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            )
+            .with_state(requests.clone());
+
+        let url = test_server::serve(app).await;
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_live() {
+        let (url, requests) = mock_site(3).await;
+        let check = LiveCheck::new(Duration::from_secs(5), Duration::from_millis(10));
+
+        let result = check.wait(&format!("{url}/plog/2023/10/test-post")).await;
+
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_wait_times_out() {
+        let (url, _) = mock_site(usize::MAX).await;
+        let check = LiveCheck::new(Duration::from_millis(50), Duration::from_millis(10));
+        let post_url = format!("{url}/plog/2023/10/test-post");
+
+        assert_eq!(
+            check.wait(&post_url).await,
+            Err(format!(
+                "{post_url} was not live after 0s, last answer: 404 Not Found"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_check_timeout() {
+        assert_eq!(
+            check_timeout(Duration::from_secs(60)),
+            Ok(Duration::from_secs(60))
+        );
+        assert_eq!(check_timeout(MAX_TIMEOUT), Ok(MAX_TIMEOUT));
+        assert_eq!(
+            check_timeout(Duration::from_secs(300)),
+            Err(
+                "Invalid LIVE_CHECK_TIMEOUT: 300s would outlast the function, use at most 240s"
+                    .to_string()
+            )
+        );
+    }
+}
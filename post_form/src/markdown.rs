@@ -0,0 +1,335 @@
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+// Elements that run code, load other pages or restyle the whole post
+const DANGEROUS_ELEMENTS: [&str; 12] = [
+    "script", "style", "iframe", "frame", "object", "embed", "form", "input", "button", "link",
+    "meta", "base",
+];
+
+// Links without a scheme are relative and always allowed
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+// Attributes that browsers load or follow as URLs
+const URL_ATTRIBUTES: [&str; 10] = [
+    "href",
+    "src",
+    "srcset",
+    "action",
+    "formaction",
+    "poster",
+    "background",
+    "cite",
+    "data",
+    "xlink:href",
+];
+
+// Characters that start emphasis, links, code, HTML or tables inside a line
+const INLINE_SPECIAL: &str = "\\`*_[]<>|~&";
+
+/// Escapes text so it is shown as is inside Markdown, like a caption inside `*...*`.
+pub fn escape_inline(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+    {
+        if INLINE_SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The text of a description without its Markdown, for announcements that show it as typed.
+pub fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(part) | Event::Code(part) => text.push_str(&part),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak | Event::End(TagEnd::Item) => text.push('\n'),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock) => {
+                text.push_str("\n\n")
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Parses a description as CommonMark and rejects what would break the page around it:
+/// thematic breaks and underlined headings, which look like front matter, and unsafe HTML or links.
+pub fn validate_description(text: &str) -> Result<(), String> {
+    for (event, range) in Parser::new(text).into_offset_iter() {
+        let line = text[..range.start].matches('\n').count() + 1;
+        let source = &text[range];
+        match event {
+            Event::Rule => {
+                return Err(format!("Thematic break (---) on line {line}"));
+            }
+            Event::Start(Tag::Heading { .. }) if !source.trim_start().starts_with('#') => {
+                return Err(format!(
+                    "Underlined heading on line {line}, use # for headings"
+                ));
+            }
+            Event::Start(Tag::HtmlBlock) | Event::Html(_) | Event::InlineHtml(_) => {
+                if let Some(reason) = dangerous_html(source) {
+                    return Err(format!("{reason} on line {line}"));
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. })
+                if is_dangerous_url(&dest_url) =>
+            {
+                return Err(format!("Unsafe link {dest_url} on line {line}"));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_dangerous_url(url: &str) -> bool {
+    // Browsers decode entities and skip tabs and newlines, so `java&#x09;script:` still runs
+    let url: String = decode_entities(url)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    match url.split_once(':') {
+        Some((scheme, _)) if is_scheme(scheme) => !SAFE_SCHEMES.contains(&scheme),
+        _ => false,
+    }
+}
+
+// A colon after anything else, like in `notes/day:1`, is part of a relative path
+fn is_scheme(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match decode_entity(rest) {
+            Some((c, length)) => {
+                decoded.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// The character of the entity starting `text`, and how long the entity is.
+// Numeric entities don't need the closing semicolon.
+fn decode_entity(text: &str) -> Option<(char, usize)> {
+    let body = &text[1..];
+    let (digits, radix, prefix) = if let Some(digits) = body.strip_prefix("#x") {
+        (digits, 16, 3)
+    } else if let Some(digits) = body.strip_prefix("#X") {
+        (digits, 16, 3)
+    } else if let Some(digits) = body.strip_prefix('#') {
+        (digits, 10, 2)
+    } else {
+        let end = body.find(';')?;
+        let c = match &body[..end] {
+            "colon" => ':',
+            "tab" => '\t',
+            "newline" => '\n',
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => return None,
+        };
+        return Some((c, end + 2));
+    };
+    let length = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let c = u32::from_str_radix(&digits[..length], radix)
+        .ok()
+        .and_then(char::from_u32)?;
+    let semicolon = usize::from(digits[length..].starts_with(';'));
+    Some((c, prefix + length + semicolon))
+}
+
+fn dangerous_html(html: &str) -> Option<String> {
+    let html = html.to_lowercase();
+
+    for tag in html.split('<').skip(1) {
+        let tag = tag.trim_start_matches('/');
+        let name: String = tag
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        if DANGEROUS_ELEMENTS.contains(&name.as_str()) {
+            return Some(format!("Unsafe HTML element <{name}>"));
+        }
+
+        for (attribute, value) in attributes(&tag[name.len()..]) {
+            if attribute.starts_with("on")
+                || (URL_ATTRIBUTES.contains(&attribute) && is_dangerous_url(value))
+            {
+                return Some(format!("Unsafe HTML attribute {attribute}"));
+            }
+        }
+    }
+    None
+}
+
+// The attributes of a tag up to its closing `>`, with quoted values unquoted
+fn attributes(tag: &str) -> Vec<(&str, &str)> {
+    let mut attributes = vec![];
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() || rest.starts_with('>') {
+            return attributes;
+        }
+
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>')
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(end);
+        let Some(after) = after.trim_start().strip_prefix('=') else {
+            attributes.push((name, ""));
+            rest = after;
+            continue;
+        };
+
+        let after = after.trim_start();
+        let (value, after) = match after.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let quoted = &after[1..];
+                let end = quoted.find(quote).unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+            }
+            _ => after.split_at(
+                after
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(after.len()),
+            ),
+        };
+        attributes.push((name, value));
+        rest = after;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_escape_inline() {
+        let cases = vec![
+            ("Top of the world", "Top of the world"),
+            ("snow_line *very* cold", "snow\\_line \\*very\\* cold"),
+            ("[link](url) <b>", "\\[link\\](url) \\<b\\>"),
+            ("Two\nlines", "Two lines"),
+            ("Rain & sun: 10.5 km!", "Rain \\& sun: 10.5 km!"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(escape_inline(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_plain_text() {
+        let cases = vec![
+            (
+                "A *long* day, see [the map](https://example.com/map).\n\n- Rain\n- Sun",
+                "A long day, see the map.\n\nRain\nSun",
+            ),
+            (
+                "# Morning\nCold and **wet**\nagain",
+                "Morning\n\nCold and wet again",
+            ),
+            ("", ""),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(plain_text(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_validate_description() {
+        let cases = vec![
+            ("A *long* day with [a map](https://example.com).", Ok(())),
+            ("# Morning\n\nCold<br>and wet", Ok(())),
+            (
+                "First part\n\n---\n\nSecond part",
+                Err("Thematic break (---) on line 3"),
+            ),
+            (
+                "Title\n---\nText",
+                Err("Underlined heading on line 1, use # for headings"),
+            ),
+            (
+                "Nice\n\n<script>alert(1)</script>",
+                Err("Unsafe HTML element <script> on line 3"),
+            ),
+            (
+                "Look <img src=x onerror=alert(1)>",
+                Err("Unsafe HTML attribute onerror on line 1"),
+            ),
+            (
+                "<a href=\"javascript:alert(1)\">x</a>",
+                Err("Unsafe HTML attribute href on line 1"),
+            ),
+            (
+                "[x](javascript:alert(1))",
+                Err("Unsafe link javascript:alert(1) on line 1"),
+            ),
+            (
+                "<a href=\"&#106;avascript:alert(1)\">x</a>",
+                Err("Unsafe HTML attribute href on line 1"),
+            ),
+            (
+                "<a href=\"java&#x09;script:alert(1)\">x</a>",
+                Err("Unsafe HTML attribute href on line 1"),
+            ),
+            (
+                "<a title=\"a > b\" href=\" javascript:alert(1)\">x</a>",
+                Err("Unsafe HTML attribute href on line 1"),
+            ),
+            (
+                "<img src=\"data:image/svg+xml,x\">",
+                Err("Unsafe HTML attribute src on line 1"),
+            ),
+            (
+                "[x](vbscript&colon;x)",
+                Err("Unsafe link vbscript:x on line 1"),
+            ),
+            (
+                "<img alt=\"Note: cold\" src=\"/images/a.jpg\"> [mail](mailto:a@example.com)",
+                Ok(()),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                validate_description(input),
+                expected.map_err(String::from),
+                "{input}"
+            );
+        }
+    }
+}
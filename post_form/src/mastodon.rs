@@ -0,0 +1,612 @@
+use crate::notifier::{Announcement, Delivery, Notifier, Notify};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// Mastodon's default status length
+const MAX_LENGTH: usize = 500;
+/// Mastodon refuses statuses scheduled less than five minutes ahead.
+const MIN_SCHEDULE: chrono::Duration = chrono::Duration::minutes(5);
+// Characters Mastodon counts for a link
+const URL_LENGTH: usize = 23;
+// Latest and scheduled statuses searched for one about the post, the most Mastodon lists at once
+const LOOKBACK: &str = "40";
+
+#[derive(Serialize)]
+struct Status {
+    status: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    media_ids: Vec<String>,
+    visibility: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Created {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct PostedStatus {
+    id: String,
+    /// HTML, with links as `<a href="...">`.
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ScheduledStatus {
+    id: String,
+    params: ScheduledParams,
+}
+
+#[derive(Deserialize)]
+struct ScheduledParams {
+    text: String,
+}
+
+/// Posts a status with the feature image about each post.
+pub struct Mastodon {
+    client: reqwest::Client,
+    api_url: String,
+    token: String,
+    visibility: String,
+}
+
+impl Mastodon {
+    pub fn new(api_url: String, token: String) -> Self {
+        Mastodon {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            visibility: "public".to_string(),
+        }
+    }
+
+    // MASTODON_URL is the instance, like https://mastodon.social, and MASTODON_ACCESS_TOKEN needs
+    // the read:accounts, read:statuses, write:statuses and write:media scopes.
+    // MASTODON_VISIBILITY defaults to public.
+    pub fn from_env() -> Result<Self, String> {
+        let api_url = std::env::var("MASTODON_URL").map_err(|_| "MASTODON_URL not set")?;
+        let token =
+            std::env::var("MASTODON_ACCESS_TOKEN").map_err(|_| "MASTODON_ACCESS_TOKEN not set")?;
+        let mut mastodon = Mastodon::new(api_url, token);
+        if let Ok(visibility) = std::env::var("MASTODON_VISIBILITY") {
+            match visibility.trim() {
+                "public" | "unlisted" | "private" => {
+                    mastodon.visibility = visibility.trim().to_string()
+                }
+                _ => return Err(format!("Invalid MASTODON_VISIBILITY: {visibility}")),
+            }
+        }
+        Ok(mastodon)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, String> {
+        let response = self
+            .client
+            .get(format!("{}{path}", self.api_url))
+            .query(query)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to get {path}: {status}: {body}"));
+        }
+        response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse {path}: {err}"))
+    }
+
+    // A posted or scheduled status about the post, so publishing it again doesn't repeat it
+    async fn existing_status(&self, post_url: &str) -> Result<Option<String>, String> {
+        let account: Account = self.get("/api/v1/accounts/verify_credentials", &[]).await?;
+        let statuses: Vec<PostedStatus> = self
+            .get(
+                &format!("/api/v1/accounts/{}/statuses", account.id),
+                &[("limit", LOOKBACK)],
+            )
+            .await?;
+        let link = format!("href=\"{}\"", post_url.replace('&', "&amp;"));
+        if let Some(status) = statuses
+            .into_iter()
+            .find(|status| status.content.contains(&link))
+        {
+            return Ok(Some(status.id));
+        }
+
+        let scheduled: Vec<ScheduledStatus> = self
+            .get("/api/v1/scheduled_statuses", &[("limit", LOOKBACK)])
+            .await?;
+        Ok(scheduled
+            .into_iter()
+            .find(|status| {
+                status
+                    .params
+                    .text
+                    .split_whitespace()
+                    .any(|word| word == post_url)
+            })
+            .map(|status| status.id))
+    }
+
+    // Uploads the image with its alt text and returns the media ID
+    async fn upload_media(&self, image_url: &str, alt_text: &str) -> Result<String, String> {
+        let image = self
+            .client
+            .get(image_url)
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+        if !image.status().is_success() {
+            return Err(format!(
+                "Failed to download {image_url}: {}",
+                image.status()
+            ));
+        }
+        let mime = image
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|mime| mime.to_str().ok())
+            .unwrap_or("image/jpeg")
+            .to_string();
+        let bytes = image
+            .bytes()
+            .await
+            .map_err(|err| format!("Failed to download {image_url}: {err}"))?;
+
+        let file_name = image_url.rsplit('/').next().unwrap_or("image").to_string();
+        let file = reqwest::multipart::Part::bytes(bytes.to_vec())
+            .file_name(file_name)
+            .mime_str(&mime)
+            .map_err(|err| format!("Invalid image type {mime}: {err}"))?;
+        let form = reqwest::multipart::Form::new()
+            .part("file", file)
+            .text("description", alt_text.to_string());
+
+        let response = self
+            .client
+            .post(format!("{}/api/v2/media", self.api_url))
+            .bearer_auth(&self.token)
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to upload media: {status}: {body}"));
+        }
+        let media: Created = response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse media: {err}"))?;
+        Ok(media.id)
+    }
+}
+
+/// The status text, with the description, or a title too long for anything else, shortened to fit.
+fn status_text(announcement: &Announcement) -> String {
+    let url = &announcement.post_url;
+    let title = fit(
+        announcement.title.trim(),
+        MAX_LENGTH.saturating_sub(length(url) + 2),
+    );
+    // A description needs room for at least a letter and the ellipsis
+    let room = MAX_LENGTH.saturating_sub(length(&title) + length(url) + 4);
+    let description = fit(announcement.description.trim(), room);
+    if description.is_empty() || room < 2 {
+        return format!(
+            "{title}
+
+{url}"
+        );
+    }
+    format!(
+        "{title}
+
+{description}
+
+{url}"
+    )
+}
+
+// `text` as it is when it fits in `room`, otherwise shortened with an ellipsis
+fn fit(text: &str, room: usize) -> String {
+    if length(text) <= room {
+        return text.to_string();
+    }
+    format!("{}…", shorten(text, room.saturating_sub(1)).trim_end())
+}
+
+// Mastodon counts every link as URL_LENGTH characters, however long it is
+fn length(text: &str) -> usize {
+    text.split_inclusive(char::is_whitespace)
+        .map(|word| {
+            let link = word.trim_end();
+            if is_link(link) {
+                URL_LENGTH + word[link.len()..].chars().count()
+            } else {
+                word.chars().count()
+            }
+        })
+        .sum()
+}
+
+fn is_link(word: &str) -> bool {
+    word.starts_with("http://") || word.starts_with("https://")
+}
+
+// The start of `text` that is at most `room` long, never cutting a link
+fn shorten(text: &str, room: usize) -> String {
+    let mut shortened = String::new();
+    let mut used = 0;
+    for word in text.split_inclusive(char::is_whitespace) {
+        if used + length(word) <= room {
+            shortened.push_str(word);
+            used += length(word);
+            continue;
+        }
+        if !is_link(word.trim_end()) {
+            shortened.extend(word.chars().take(room - used));
+        }
+        break;
+    }
+    shortened
+}
+
+// None posts right away
+fn scheduled_at(when: &Notify, live: bool) -> Option<DateTime<Utc>> {
+    let earliest = Utc::now() + MIN_SCHEDULE;
+    match (when, live) {
+        (Notify::Never, _) | (Notify::AfterDelay, true) => None,
+        (Notify::AfterDelay, false) => Some(earliest),
+        // Mastodon refuses times closer than MIN_SCHEDULE, so those go out a little late rather than early
+        (Notify::At(time), _) => Some((*time).max(earliest)),
+    }
+}
+
+#[async_trait]
+impl Notifier for Mastodon {
+    fn name(&self) -> &'static str {
+        "Mastodon"
+    }
+
+    async fn notify(&self, announcement: &Announcement) -> Result<Delivery, String> {
+        if announcement.when == Notify::Never {
+            return Ok(Delivery::Skipped("not requested".to_string()));
+        }
+
+        if let Some(id) = self.existing_status(&announcement.post_url).await? {
+            return Ok(Delivery::Skipped(format!(
+                "status {id} is already about the post"
+            )));
+        }
+
+        // Only uploaded once a status will be posted, so no media is left unused
+        let mut media_ids = vec![];
+        if !announcement.image_url.is_empty() {
+            media_ids.push(
+                self.upload_media(&announcement.image_url, &announcement.image_alt_text)
+                    .await?,
+            );
+        }
+        let status = Status {
+            status: status_text(announcement),
+            media_ids,
+            visibility: self.visibility.clone(),
+            scheduled_at: scheduled_at(&announcement.when, announcement.live)
+                .map(|time| time.to_rfc3339()),
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/api/v1/statuses", self.api_url))
+            .bearer_auth(&self.token)
+            .json(&status)
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+        let code = response.status();
+        if !code.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to post status: {code}: {body}"));
+        }
+        let created: Created = response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse status: {err}"))?;
+
+        info!(
+            "Posted status {} about {}",
+            created.id, announcement.post_url
+        );
+        Ok(Delivery::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use axum::extract::{Multipart, State};
+    use axum::http::{HeaderMap, StatusCode, header};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use std::sync::{Arc, Mutex};
+
+    type Received = Arc<Mutex<Vec<Value>>>;
+
+    fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+        match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+            Some("Bearer secret") => Ok(()),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    // Statuses as Mastodon lists them, with links in the HTML content
+    fn listed(received: &Received, scheduled: bool) -> Json<Vec<Value>> {
+        let statuses = received.lock().unwrap();
+        Json(
+            statuses
+                .iter()
+                .enumerate()
+                .filter(|(_, status)| {
+                    status.get("status").is_some()
+                        && status.get("scheduled_at").is_some() == scheduled
+                })
+                .map(|(id, status)| {
+                    let text = status["status"].as_str().unwrap();
+                    if scheduled {
+                        return json!({ "id": id.to_string(), "params": { "text": text } });
+                    }
+                    let content = text
+                        .split_whitespace()
+                        .map(|word| {
+                            if word.starts_with("https://") {
+                                format!("<a href=\"{word}\">{word}</a>")
+                            } else {
+                                word.to_string()
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    json!({ "id": id.to_string(), "content": format!("<p>{content}</p>") })
+                })
+                .collect(),
+        )
+    }
+
+    // A Mastodon instance that also serves the feature image
+    async fn mock_mastodon() -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/images/feature.jpg",
+                get(|| async { ([(header::CONTENT_TYPE, "image/jpeg")], "jpeg bytes") }),
+            )
+            .route(
+                "/api/v1/accounts/verify_credentials",
+                get(|headers: HeaderMap| async move {
+                    authorized(&headers)?;
+                    Ok::<_, StatusCode>(Json(json!({ "id": "1" })))
+                }),
+            )
+            .route(
+                "/api/v1/accounts/1/statuses",
+                get(
+                    |State(received): State<Received>, headers: HeaderMap| async move {
+                        authorized(&headers)?;
+                        Ok::<_, StatusCode>(listed(&received, false))
+                    },
+                ),
+            )
+            .route(
+                "/api/v1/scheduled_statuses",
+                get(
+                    |State(received): State<Received>, headers: HeaderMap| async move {
+                        authorized(&headers)?;
+                        Ok::<_, StatusCode>(listed(&received, true))
+                    },
+                ),
+            )
+            .route(
+                "/api/v2/media",
+                post(
+                    |State(received): State<Received>,
+                     headers: HeaderMap,
+                     mut multipart: Multipart| async move {
+                        authorized(&headers)?;
+                        let mut media = json!({});
+                        while let Some(field) = multipart.next_field().await.unwrap() {
+                            let name = field.name().unwrap_or_default().to_string();
+                            let file_name = field.file_name().map(str::to_string);
+                            let text = field.text().await.unwrap();
+                            media[name] = json!(file_name.map_or(text.clone(), |file_name| {
+                                format!("{file_name}: {text}")
+                            }));
+                        }
+                        received.lock().unwrap().push(media);
+                        Ok::<_, StatusCode>(Json(json!({ "id": "7", "type": "image" })))
+                    },
+                ),
+            )
+            .route(
+                "/api/v1/statuses",
+                post(
+                    |State(received): State<Received>,
+                     headers: HeaderMap,
+                     Json(status): Json<Value>| async move {
+                        authorized(&headers)?;
+                        received.lock().unwrap().push(status);
+                        Ok::<_, StatusCode>(Json(json!({ "id": "42" })))
+                    },
+                ),
+            )
+            .with_state(received.clone());
+
+        let url = test_server::serve(app).await;
+        (url, received)
+    }
+
+    fn announcement(url: &str) -> Announcement {
+        Announcement {
+            title: "Test Post".to_string(),
+            description: "A ride".to_string(),
+            image_url: format!("{url}/images/feature.jpg"),
+            image_alt_text: "A bike by the fjord".to_string(),
+            post_url: "https://example.com/test-post".to_string(),
+            list_ids: None,
+            when: Notify::AfterDelay,
+            live: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let (url, received) = mock_mastodon().await;
+        let mastodon = Mastodon::new(url.clone(), "secret".to_string());
+
+        assert_eq!(
+            mastodon.notify(&announcement(&url)).await,
+            Ok(Delivery::Sent)
+        );
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                json!({
+                    "file": "feature.jpg: jpeg bytes",
+                    "description": "A bike by the fjord",
+                }),
+                json!({
+                    "status": "Test Post\n\nA ride\n\nhttps://example.com/test-post",
+                    "media_ids": ["7"],
+                    "visibility": "public",
+                }),
+            ]
+        );
+
+        assert_eq!(
+            mastodon.notify(&announcement(&url)).await,
+            Ok(Delivery::Skipped(
+                "status 1 is already about the post".to_string()
+            ))
+        );
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_notify_scheduled() {
+        let (url, received) = mock_mastodon().await;
+        let mastodon = Mastodon::new(url.clone(), "secret".to_string());
+        let scheduled = Announcement {
+            image_url: String::new(),
+            when: Notify::At(Utc::now() + chrono::Duration::days(1)),
+            ..announcement(&url)
+        };
+
+        assert_eq!(mastodon.notify(&scheduled).await, Ok(Delivery::Sent));
+        assert_eq!(
+            mastodon.notify(&scheduled).await,
+            Ok(Delivery::Skipped(
+                "status 0 is already about the post".to_string()
+            ))
+        );
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_notify_fails() {
+        let (url, received) = mock_mastodon().await;
+        let mastodon = Mastodon::new(url.clone(), "wrong".to_string());
+        let skipped = Announcement {
+            when: Notify::Never,
+            ..announcement(&url)
+        };
+
+        assert_eq!(
+            mastodon.notify(&skipped).await,
+            Ok(Delivery::Skipped("not requested".to_string()))
+        );
+        assert_eq!(
+            mastodon.notify(&announcement(&url)).await,
+            Err(
+                "Failed to get /api/v1/accounts/verify_credentials: 401 Unauthorized: ".to_string()
+            )
+        );
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_status_text() {
+        let announcement = |description: String, post_url: &str| Announcement {
+            title: "Test Post".to_string(),
+            description,
+            image_url: String::new(),
+            image_alt_text: String::new(),
+            post_url: post_url.to_string(),
+            list_ids: None,
+            when: Notify::AfterDelay,
+            live: true,
+        };
+        let post_url = "https://example.com/test-post";
+
+        let text = status_text(&announcement("Pedal ".repeat(100), post_url));
+        assert_eq!(length(&text), MAX_LENGTH);
+        assert!(
+            text.ends_with(" P…\n\nhttps://example.com/test-post"),
+            "{text}"
+        );
+        assert_eq!(
+            status_text(&announcement(" ".to_string(), post_url)),
+            "Test Post\n\nhttps://example.com/test-post"
+        );
+
+        // Links count as 23 characters, and are left out rather than cut
+        let linked = status_text(&announcement(
+            format!("{}https://example.com/gallery", "Pedal ".repeat(77)),
+            &format!("https://example.com/{}", "long-".repeat(20)),
+        ));
+        assert_eq!(length(&linked), 9 + 2 + 462 + 2 + 23);
+        assert!(linked.contains(" Pedal…\n\n"), "{linked}");
+
+        // A title too long for the description leaves only room for the link
+        let titled = status_text(&Announcement {
+            title: "Pedal ".repeat(100),
+            ..announcement("A ride".to_string(), post_url)
+        });
+        assert_eq!(
+            titled,
+            format!("{}…\n\n{post_url}", "Pedal ".repeat(79).trim_end())
+        );
+    }
+
+    #[test]
+    fn test_scheduled_at() {
+        let later = Utc::now() + chrono::Duration::days(1);
+        let soon = Utc::now() + chrono::Duration::minutes(1);
+
+        assert_eq!(scheduled_at(&Notify::AfterDelay, true), None);
+        assert!(scheduled_at(&Notify::AfterDelay, false).unwrap() > soon);
+        assert_eq!(scheduled_at(&Notify::At(later), true), Some(later));
+        assert!(scheduled_at(&Notify::At(soon), true).unwrap() > soon);
+        assert!(scheduled_at(&Notify::At(soon), false).unwrap() > soon);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::tera::FRONT_MATTER_KEYS;
+use serde_yaml::Value;
+use std::collections::BTreeSet;
+
+/// Form fields starting with this are written to the front matter without the prefix.
+pub const PREFIX: &str = "meta_";
+
+// Keys that change how Jekyll builds the post, besides those plogtion writes
+const JEKYLL_KEYS: [&str; 4] = ["layout", "permalink", "published", "slug"];
+
+/// The custom front matter keys that are accepted, any key when no list is configured.
+#[derive(Default, Debug)]
+pub struct AllowList(Option<BTreeSet<String>>);
+
+impl AllowList {
+    pub fn parse(names: &str) -> Self {
+        AllowList(Some(
+            names
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+        ))
+    }
+
+    // META_FIELDS limits the custom front matter keys, like "gear, weather, companions"
+    pub fn from_env() -> Self {
+        match std::env::var("META_FIELDS") {
+            Ok(names) => AllowList::parse(&names),
+            Err(_) => AllowList::default(),
+        }
+    }
+
+    pub fn check(&self, name: &str) -> Result<(), String> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(format!(
+                "Invalid field {PREFIX}{name}, use lowercase letters, digits and underscores"
+            ));
+        }
+        if is_reserved(name) {
+            return Err(format!("Field {PREFIX}{name} would replace {name}"));
+        }
+        match &self.0 {
+            Some(names) if !names.contains(name) => {
+                Err(format!("Unexpected field: {PREFIX}{name}"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the front matter already has `name`, or it changes how Jekyll builds the post.
+pub fn is_reserved(name: &str) -> bool {
+    FRONT_MATTER_KEYS.contains(&name) || JEKYLL_KEYS.contains(&name)
+}
+
+/// Numbers become YAML numbers and `[a, b]` a list, anything else stays text.
+pub fn parse_value(text: &str) -> Value {
+    let text = text.trim();
+    if let Some(items) = text
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        return Value::Sequence(
+            items
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(parse_scalar)
+                .collect(),
+        );
+    }
+    parse_scalar(text)
+}
+
+fn parse_scalar(text: &str) -> Value {
+    if let Ok(number) = text.parse::<i64>() {
+        return Value::from(number);
+    }
+    match text.parse::<f64>() {
+        Ok(number) if number.is_finite() => Value::from(number),
+        _ => Value::from(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_value() {
+        let cases = vec![
+            ("Rain, then sun", Value::from("Rain, then sun")),
+            (" 3 ", Value::from(3)),
+            ("-1.5", Value::from(-1.5)),
+            ("NaN", Value::from("NaN")),
+            (
+                "[tent, stove, 2]",
+                Value::Sequence(vec![
+                    Value::from("tent"),
+                    Value::from("stove"),
+                    Value::from(2),
+                ]),
+            ),
+            ("[]", Value::Sequence(vec![])),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_value(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let any = AllowList::default();
+        assert_eq!(any.check("weather"), Ok(()));
+        assert_eq!(
+            any.check("title"),
+            Err("Field meta_title would replace title".to_string())
+        );
+        assert_eq!(
+            any.check("Gear-list"),
+            Err(
+                "Invalid field meta_Gear-list, use lowercase letters, digits and underscores"
+                    .to_string()
+            )
+        );
+
+        let listed = AllowList::parse("gear, weather");
+        assert_eq!(listed.check("gear"), Ok(()));
+        assert_eq!(
+            listed.check("companions"),
+            Err("Unexpected field: meta_companions".to_string())
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::brevo::Brevo;
+use crate::dates::PostDate;
+use crate::mastodon::Mastodon;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+/// Whether and when a post is announced, from the `notify` form field.
+#[derive(Default, Debug, PartialEq)]
+pub enum Notify {
+    #[default]
+    AfterDelay,
+    Never,
+    At(DateTime<Utc>),
+}
+
+impl Notify {
+    /// Empty or `yes` announces after the configured delay, `no` skips, and a time schedules for then.
+    pub fn parse(text: &str, timezone: Tz) -> Result<Self, String> {
+        let invalid =
+            || format!("Invalid notify {text}, use yes, no or a time like YYYY-MM-DDTHH:MM");
+        match text.trim().to_lowercase().as_str() {
+            "" | "yes" | "on" | "true" => Ok(Notify::AfterDelay),
+            "no" | "off" | "false" => Ok(Notify::Never),
+            _ => match PostDate::parse(text, timezone).map_err(|_| invalid())? {
+                PostDate::Time(time) => Ok(Notify::At(time.with_timezone(&Utc))),
+                PostDate::Day(_) => Err(invalid()),
+            },
+        }
+    }
+}
+
+/// A published post, as told to subscribers and followers.
+pub struct Announcement {
+    pub title: String,
+    pub description: String,
+    pub image_url: String,
+    pub image_alt_text: String,
+    pub post_url: String,
+    /// Newsletter lists chosen for the post, the configured lists when `None`.
+    pub list_ids: Option<Vec<i64>>,
+    pub when: Notify,
+    /// Whether the post was seen on the site, so it can be announced right away.
+    pub live: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    Sent,
+    /// Nothing was sent, with the reason.
+    Skipped(String),
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn notify(&self, announcement: &Announcement) -> Result<Delivery, String>;
+}
+
+// NOTIFIERS lists where posts are announced, like "brevo, mastodon", and defaults to brevo
+pub fn from_env() -> Result<Vec<Box<dyn Notifier>>, String> {
+    let names = std::env::var("NOTIFIERS").unwrap_or_else(|_| "brevo".to_string());
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| -> Result<Box<dyn Notifier>, String> {
+            match name {
+                "brevo" => Ok(Box::new(Brevo::from_env()?)),
+                "mastodon" => Ok(Box::new(Mastodon::from_env()?)),
+                other => Err(format!("Unknown notifier: {other}")),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_notify() {
+        let oslo: Tz = "Europe/Oslo".parse().unwrap();
+        let at = "2023-10-01T16:00:00Z".parse().unwrap();
+        let invalid = |text: &str| {
+            Err(format!(
+                "Invalid notify {text}, use yes, no or a time like YYYY-MM-DDTHH:MM"
+            ))
+        };
+        let cases = vec![
+            ("", Ok(Notify::AfterDelay)),
+            ("on", Ok(Notify::AfterDelay)),
+            (" No ", Ok(Notify::Never)),
+            ("2023-10-01T18:00", Ok(Notify::At(at))),
+            ("2023-10-01", invalid("2023-10-01")),
+            ("tomorrow", invalid("tomorrow")),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(Notify::parse(input, oslo), expected, "{input}");
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use crate::dates::PostDate;
+use crate::publisher::{File, Publisher};
+use crate::site::SiteConfig;
+use crate::{strava, taxonomy};
+use chrono::NaiveDate;
+use futures_util::{StreamExt, stream};
+use log::warn;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub const POSTS_DIR: &str = "_posts";
+
+/// The parts of an existing post's front matter we look at.
+#[derive(Deserialize, Clone, Default, Debug, PartialEq)]
+#[serde(default)]
+pub struct PostFrontMatter {
+    #[serde(deserialize_with = "text")]
+    pub title: String,
+    #[serde(deserialize_with = "terms")]
+    pub categories: Vec<String>,
+    #[serde(deserialize_with = "terms")]
+    pub tags: Vec<String>,
+    #[serde(deserialize_with = "activity_id")]
+    pub strava: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ExistingPost {
+    pub path: String,
+    pub front_matter: PostFrontMatter,
+}
+
+impl ExistingPost {
+    /// The post's URL, from a path like `_posts/2023-10-01-test-post.md` and its categories.
+    pub fn url(&self, site: &SiteConfig) -> Option<String> {
+        let file_name = self.path.rsplit('/').next()?;
+        let (name, _) = file_name.rsplit_once('.')?;
+        let date = NaiveDate::parse_from_str(name.get(..10)?, "%Y-%m-%d").ok()?;
+        let slug = name.get(11..)?;
+        Some(site.post_url(PostDate::Day(date), slug, &self.front_matter.categories))
+    }
+}
+
+pub fn find_by_strava(posts: &[ExistingPost], id: u64) -> Option<&ExistingPost> {
+    posts
+        .iter()
+        .find(|post| post.front_matter.strava == Some(id))
+}
+
+/// A post at the same path, whatever its extension, or one the site serves at the same URL.
+pub fn find_by_path_or_url<'a>(
+    posts: &'a [ExistingPost],
+    site: &SiteConfig,
+    path: &str,
+    url: &str,
+) -> Option<&'a ExistingPost> {
+    posts
+        .iter()
+        .find(|post| stem(&post.path) == stem(path) || post.url(site).as_deref() == Some(url))
+}
+
+fn stem(path: &str) -> &str {
+    path.rsplit_once('.').map_or(path, |(stem, _)| stem)
+}
+
+// YAML reads titles like 2023 or yes as numbers and booleans
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(
+        match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+            Some(serde_yaml::Value::String(text)) => text,
+            Some(serde_yaml::Value::Number(number)) => number.to_string(),
+            Some(serde_yaml::Value::Bool(value)) => value.to_string(),
+            _ => String::new(),
+        },
+    )
+}
+
+// Older posts have categories as a comma separated string, newer ones as a YAML sequence
+fn terms<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Terms {
+        List(Vec<String>),
+        Text(String),
+    }
+
+    Ok(match Option::<Terms>::deserialize(deserializer)? {
+        Some(Terms::List(terms)) => taxonomy::normalize(terms),
+        Some(Terms::Text(text)) => taxonomy::parse_terms(&text),
+        None => vec![],
+    })
+}
+
+// YAML reads a bare activity ID as a number, and some older posts link to the activity
+fn activity_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    Ok(
+        match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+            Some(serde_yaml::Value::Number(number)) => number.as_u64(),
+            Some(serde_yaml::Value::String(text)) => strava::parse_activity_id(&text).ok(),
+            _ => None,
+        },
+    )
+}
+
+pub fn parse_front_matter(content: &str) -> Result<PostFrontMatter, String> {
+    let front_matter = content
+        .strip_prefix("---\n")
+        .and_then(|rest| rest.split_once("\n---"))
+        .map(|(front_matter, _)| front_matter)
+        .ok_or_else(|| "Missing front matter".to_string())?;
+
+    serde_yaml::from_str(front_matter).map_err(|err| format!("Invalid front matter: {err}"))
+}
+
+// Front matter by git blob ID, so only posts that are new or changed are read
+static CACHE: Mutex<Option<HashMap<String, PostFrontMatter>>> = Mutex::new(None);
+
+// Posts read at once when the cache is cold, few enough to stay clear of API rate limits
+const CONCURRENT_READS: usize = 8;
+
+/// Reads the front matter of every post in the repository, skipping posts we can't parse.
+pub async fn scan(publisher: &dyn Publisher) -> Result<Vec<ExistingPost>, String> {
+    let files: Vec<File> = publisher
+        .list_files(POSTS_DIR)
+        .await?
+        .into_iter()
+        .filter(|file| file.path.ends_with(".md") || file.path.ends_with(".markdown"))
+        .collect();
+    let unread: Vec<File> = {
+        let cache = CACHE.lock().unwrap();
+        files
+            .iter()
+            .filter(|file| {
+                !cache
+                    .as_ref()
+                    .is_some_and(|cache| cache.contains_key(&file.id))
+            })
+            .cloned()
+            .collect()
+    };
+
+    let reads: Vec<_> = stream::iter(unread)
+        .map(|file| async move {
+            let content = publisher.read_file(&file.path).await;
+            (file, content)
+        })
+        .buffered(CONCURRENT_READS)
+        .collect()
+        .await;
+    let mut read = HashMap::new();
+    let mut failure = None;
+    for (file, content) in reads {
+        match content {
+            Ok(Some(content)) => match parse_front_matter(&content.replace("\r\n", "\n")) {
+                Ok(front_matter) => {
+                    read.insert(file.id.clone(), front_matter);
+                }
+                Err(err) => warn!("Skipping {}: {err}", file.path),
+            },
+            Ok(None) => {}
+            Err(err) => {
+                failure.get_or_insert(err);
+            }
+        }
+    }
+
+    // Whatever was read is kept, also when another read failed
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+    cache.extend(read);
+    if let Some(err) = failure {
+        return Err(err);
+    }
+
+    // Only the current posts are kept, so the cache doesn't outgrow the repository
+    cache.retain(|id, _| files.iter().any(|file| &file.id == id));
+    Ok(files
+        .into_iter()
+        .filter_map(|file| {
+            Some(ExistingPost {
+                front_matter: cache.get(&file.id)?.clone(),
+                path: file.path,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::Changes;
+    use async_trait::async_trait;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_parse_front_matter() {
+        let cases = vec![
+            (
+                "---\ntitle: \"Old\"\ncategories: \"Bikepacking, norway\"\n---\n\nBody",
+                "Old",
+                vec!["bikepacking", "norway"],
+                vec![],
+                None,
+            ),
+            (
+                "---\ntitle: New\ncategories:\n- hiking\ntags:\n- Summit\n- summit\n---\n",
+                "New",
+                vec!["hiking"],
+                vec!["summit"],
+                None,
+            ),
+            ("---\ntitle: 2023\n---\n", "2023", vec![], vec![], None),
+            ("---\nstrava: 123\n---\n", "", vec![], vec![], Some(123)),
+            ("---\nstrava: '123'\n---\n", "", vec![], vec![], Some(123)),
+            (
+                "---\nstrava: \"https://www.strava.com/activities/123\"\n---\n",
+                "",
+                vec![],
+                vec![],
+                Some(123),
+            ),
+        ];
+
+        for (content, title, categories, tags, strava) in cases {
+            assert_eq!(
+                parse_front_matter(content),
+                Ok(PostFrontMatter {
+                    title: title.to_string(),
+                    categories: categories.into_iter().map(String::from).collect(),
+                    tags: tags.into_iter().map(String::from).collect(),
+                    strava,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_front_matter_without_front_matter() {
+        assert_eq!(
+            parse_front_matter("# Just Markdown"),
+            Err("Missing front matter".to_string())
+        );
+    }
+
+    // Serves posts from memory and counts the reads
+    #[derive(Default)]
+    struct MemoryPublisher {
+        files: Vec<(String, String, String)>,
+        reads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Publisher for MemoryPublisher {
+        async fn read_file(&self, file_path: &str) -> Result<Option<String>, String> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            match self.files.iter().find(|(path, _, _)| path == file_path) {
+                Some((_, _, content)) if content == "unreadable" => {
+                    Err(format!("Failed to read {file_path}"))
+                }
+                file => Ok(file.map(|(_, _, content)| content.clone())),
+            }
+        }
+
+        async fn list_files(&self, _: &str) -> Result<Vec<File>, String> {
+            Ok(self
+                .files
+                .iter()
+                .map(|(path, id, _)| File {
+                    path: path.clone(),
+                    id: id.clone(),
+                })
+                .collect())
+        }
+
+        async fn publish(&self, _: &Changes, _: &str) -> Result<String, String> {
+            Err("not supported".to_string())
+        }
+    }
+
+    // The scans share the cache, and each keeps only its own posts
+    static SCANS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn post(name: &str, id: &str, strava: u64) -> (String, String, String) {
+        (
+            format!("_posts/{name}.md"),
+            format!("scan-test-{id}"),
+            format!("---\nstrava: {strava}\n---\n"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_scan_reads_only_new_and_changed_posts() {
+        let _scans = SCANS.lock().await;
+        let first = MemoryPublisher {
+            files: vec![post("a", "a1", 1), post("b", "b1", 2)],
+            ..Default::default()
+        };
+        let posts = scan(&first).await.unwrap();
+        assert_eq!(posts.len(), 2);
+        assert_eq!(first.reads.load(Ordering::SeqCst), 2);
+
+        let second = MemoryPublisher {
+            files: vec![post("a", "a1", 1), post("b", "b2", 3), post("c", "c1", 4)],
+            ..Default::default()
+        };
+        let posts = scan(&second).await.unwrap();
+        assert_eq!(second.reads.load(Ordering::SeqCst), 2);
+        let stravas: Vec<_> = posts.iter().map(|post| post.front_matter.strava).collect();
+        assert_eq!(stravas, vec![Some(1), Some(3), Some(4)]);
+    }
+
+    #[tokio::test]
+    async fn test_scan_keeps_the_cache_when_a_read_fails() {
+        let _scans = SCANS.lock().await;
+        let unreadable = (
+            "_posts/e.md".to_string(),
+            "scan-test-e1".to_string(),
+            "unreadable".to_string(),
+        );
+        let first = MemoryPublisher {
+            files: vec![post("d", "d1", 5), unreadable],
+            ..Default::default()
+        };
+        assert_eq!(
+            scan(&first).await,
+            Err("Failed to read _posts/e.md".to_string())
+        );
+
+        let second = MemoryPublisher {
+            files: vec![post("d", "d1", 5), post("e", "e1", 6)],
+            ..Default::default()
+        };
+        let posts = scan(&second).await.unwrap();
+        assert_eq!(second.reads.load(Ordering::SeqCst), 1);
+        let stravas: Vec<_> = posts.iter().map(|post| post.front_matter.strava).collect();
+        assert_eq!(stravas, vec![Some(5), Some(6)]);
+    }
+
+    #[test]
+    fn test_find_by_strava() {
+        let posts = vec![
+            ExistingPost {
+                path: "_posts/2023-10-01-first-day.md".to_string(),
+                front_matter: PostFrontMatter {
+                    strava: Some(1),
+                    ..Default::default()
+                },
+            },
+            ExistingPost {
+                path: "_posts/2023-09-30-day-zero.md".to_string(),
+                front_matter: PostFrontMatter::default(),
+            },
+        ];
+
+        let site = SiteConfig {
+            url: "https://kyrremann.no".to_string(),
+            baseurl: "/plog".to_string(),
+            permalink: "/:year/:month/:title".to_string(),
+        };
+        assert_eq!(
+            find_by_strava(&posts, 1).and_then(|post| post.url(&site)),
+            Some("https://kyrremann.no/plog/2023/10/first-day".to_string())
+        );
+        assert_eq!(find_by_strava(&posts, 2), None);
+    }
+
+    #[test]
+    fn test_find_by_path_or_url() {
+        let posts = vec![ExistingPost {
+            path: "_posts/2023-10-01-first-day.markdown".to_string(),
+            front_matter: PostFrontMatter::default(),
+        }];
+        let site = SiteConfig {
+            url: "https://kyrremann.no".to_string(),
+            baseurl: "/plog".to_string(),
+            permalink: "/:year/:month/:title".to_string(),
+        };
+
+        assert_eq!(
+            find_by_path_or_url(
+                &posts,
+                &site,
+                "_posts/2023-10-01-first-day.md",
+                "https://kyrremann.no/plog/2023/10/first-day"
+            ),
+            posts.first()
+        );
+        // Another day of the month, but the permalink has no day
+        assert_eq!(
+            find_by_path_or_url(
+                &posts,
+                &site,
+                "_posts/2023-10-15-first-day.md",
+                "https://kyrremann.no/plog/2023/10/first-day"
+            ),
+            posts.first()
+        );
+        assert_eq!(
+            find_by_path_or_url(
+                &posts,
+                &site,
+                "_posts/2023-11-01-first-day.md",
+                "https://kyrremann.no/plog/2023/11/first-day"
+            ),
+            None
+        );
+    }
+}
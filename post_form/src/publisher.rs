@@ -0,0 +1,52 @@
+use crate::git::{self, GitPublisher};
+use crate::github::GitHubPublisher;
+use async_trait::async_trait;
+
+/// Files to write and files to delete, published together as one commit.
+#[derive(Default, Debug)]
+pub struct Changes {
+    /// `(path, content)` pairs, for both new and existing files.
+    pub write: Vec<(String, String)>,
+    pub delete: Vec<String>,
+}
+
+/// A file in the plog repository.
+#[derive(Clone, Debug, PartialEq)]
+pub struct File {
+    pub path: String,
+    /// The git blob ID, which only changes when the content does.
+    pub id: String,
+}
+
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Reads a file from the plog repository, or `None` if it doesn't exist.
+    async fn read_file(&self, file_path: &str) -> Result<Option<String>, String>;
+
+    /// Lists the files directly inside `dir`, sorted by path.
+    async fn list_files(&self, dir: &str) -> Result<Vec<File>, String>;
+
+    /// Commits all `changes` as a single commit, pushes it and returns the commit SHA.
+    async fn publish(&self, changes: &Changes, message: &str) -> Result<String, String>;
+}
+
+// PUBLISHER selects how posts reach the plog repository: "git" (default) clones
+// the repository, "github" talks to the GitHub Git Data API instead.
+pub async fn from_env(token: &str) -> Result<Box<dyn Publisher>, String> {
+    match std::env::var("PUBLISHER").unwrap_or_default().as_str() {
+        "" | "git" => Ok(Box::new(GitPublisher::clone(git::REPO_URL, token).await?)),
+        "github" => {
+            let api_url = std::env::var("GITHUB_API_URL")
+                .unwrap_or_else(|_| "https://api.github.com".to_string());
+            Ok(Box::new(GitHubPublisher::new(api_url, token)))
+        }
+        other => Err(format!("Unknown publisher: {other}")),
+    }
+}
+
+// Reading needs no clone, so it always goes through the GitHub API, whatever PUBLISHER is
+pub fn reader_from_env(token: &str) -> Box<dyn Publisher> {
+    let api_url =
+        std::env::var("GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string());
+    Box::new(GitHubPublisher::new(api_url, token))
+}
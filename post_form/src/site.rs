@@ -0,0 +1,209 @@
+use crate::dates::PostDate;
+use chrono::{Datelike, Timelike};
+use serde::Deserialize;
+
+pub const CONFIG_PATH: &str = "_config.yml";
+
+/// The parts of the site's Jekyll `_config.yml` that decide where a post is published.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub url: String,
+    pub baseurl: String,
+    pub permalink: String,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        SiteConfig {
+            url: String::new(),
+            baseurl: String::new(),
+            permalink: "date".to_string(),
+        }
+    }
+}
+
+impl SiteConfig {
+    pub fn parse(yaml: Option<&str>) -> Result<Self, String> {
+        match yaml {
+            // An empty file is valid YAML, but not a mapping
+            Some(yaml) => serde_yaml::from_str::<Option<SiteConfig>>(yaml)
+                .map(Option::unwrap_or_default)
+                .map_err(|err| format!("Invalid {CONFIG_PATH}: {err}")),
+            None => Ok(SiteConfig::default()),
+        }
+    }
+
+    // SITE_URL replaces url and baseurl, PERMALINK the permalink, when the site is built with other settings
+    pub fn with_env_overrides(mut self) -> Self {
+        if let Ok(site_url) = std::env::var("SITE_URL") {
+            self.url = site_url;
+            self.baseurl = String::new();
+        }
+        if let Ok(permalink) = std::env::var("PERMALINK") {
+            self.permalink = permalink;
+        }
+        self
+    }
+
+    /// The published URL of a post, expanding the permalink like Jekyll does.
+    pub fn post_url(&self, date: PostDate, slug: &str, categories: &[String]) -> String {
+        let path = expand_permalink(permalink_pattern(&self.permalink), date, slug, categories);
+        let url = self.url.trim_end_matches('/');
+        match self.baseurl.trim_matches('/') {
+            "" => format!("{url}{path}"),
+            baseurl => format!("{url}/{baseurl}{path}"),
+        }
+    }
+}
+
+// Jekyll's built-in permalink styles
+fn permalink_pattern(permalink: &str) -> &str {
+    match permalink {
+        "date" => "/:categories/:year/:month/:day/:title:output_ext",
+        "pretty" => "/:categories/:year/:month/:day/:title/",
+        "ordinal" => "/:categories/:year/:y_day/:title:output_ext",
+        "weekdate" => "/:categories/:year/W:week/:short_day/:title:output_ext",
+        "none" => "/:categories/:title:output_ext",
+        pattern => pattern,
+    }
+}
+
+fn expand_permalink(pattern: &str, date: PostDate, slug: &str, categories: &[String]) -> String {
+    let time = match date {
+        PostDate::Day(day) => day.and_hms_opt(0, 0, 0).unwrap_or_default(),
+        PostDate::Time(time) => time.naive_local(),
+    };
+
+    let mut expanded = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find(':') {
+        expanded.push_str(&rest[..start]);
+        let name_length = rest[start + 1..]
+            .find(|c: char| !c.is_ascii_lowercase() && c != '_')
+            .unwrap_or(rest.len() - start - 1);
+        let name = &rest[start + 1..start + 1 + name_length];
+        let value = match name {
+            "year" => time.format("%Y").to_string(),
+            "short_year" => time.format("%y").to_string(),
+            "month" => time.format("%m").to_string(),
+            "i_month" => time.month().to_string(),
+            "short_month" => time.format("%b").to_string(),
+            "long_month" => time.format("%B").to_string(),
+            "day" => time.format("%d").to_string(),
+            "i_day" => time.day().to_string(),
+            "y_day" => time.format("%j").to_string(),
+            "week" => time.format("%V").to_string(),
+            "short_day" => time.format("%a").to_string(),
+            "long_day" => time.format("%A").to_string(),
+            "hour" => format!("{:02}", time.hour()),
+            "minute" => format!("{:02}", time.minute()),
+            "second" => format!("{:02}", time.second()),
+            "title" | "slug" => slug.to_string(),
+            "categories" => categories
+                .iter()
+                .map(|category| category.to_lowercase().replace(' ', "%20"))
+                .collect::<Vec<_>>()
+                .join("/"),
+            "output_ext" => ".html".to_string(),
+            _ => format!(":{name}"),
+        };
+        expanded.push_str(&value);
+        rest = &rest[start + 1 + name_length..];
+    }
+    expanded.push_str(rest);
+
+    // Empty placeholders, like a post without categories, leave double slashes behind
+    let mut path = String::new();
+    for c in expanded.chars() {
+        if !(c == '/' && path.ends_with('/')) {
+            path.push(c);
+        }
+    }
+    if path.starts_with('/') {
+        path
+    } else {
+        format!("/{path}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Tz;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse() {
+        let yaml = "title: Plog\nurl: https://kyrremann.no\nbaseurl: /plog\npermalink: /:year/:month/:title\nplugins:\n- jekyll-feed\n";
+
+        assert_eq!(
+            SiteConfig::parse(Some(yaml)),
+            Ok(SiteConfig {
+                url: "https://kyrremann.no".to_string(),
+                baseurl: "/plog".to_string(),
+                permalink: "/:year/:month/:title".to_string(),
+            })
+        );
+        assert_eq!(SiteConfig::parse(Some("")), Ok(SiteConfig::default()));
+        assert_eq!(SiteConfig::parse(None), Ok(SiteConfig::default()));
+    }
+
+    #[test]
+    fn test_post_url() {
+        let date = PostDate::parse("2023-10-01T07:05", Tz::UTC).unwrap();
+        let categories = vec!["bikepacking".to_string(), "Norway".to_string()];
+        let spaced = vec!["city trip".to_string()];
+        let cases = vec![
+            (
+                "https://kyrremann.no",
+                "/plog/",
+                "/:year/:month/:title",
+                "https://kyrremann.no/plog/2023/10/test-post",
+            ),
+            (
+                "https://kyrremann.no/",
+                "",
+                "date",
+                "https://kyrremann.no/bikepacking/norway/2023/10/01/test-post.html",
+            ),
+            (
+                "https://kyrremann.no",
+                "",
+                "pretty",
+                "https://kyrremann.no/bikepacking/norway/2023/10/01/test-post/",
+            ),
+            (
+                "",
+                "/plog",
+                "/:short_year/:i_month/:i_day/:hour:minute-:slug:output_ext",
+                "/plog/23/10/1/0705-test-post.html",
+            ),
+            ("", "", "none", "/bikepacking/norway/test-post.html"),
+            ("", "", ":title/:unknown", "/test-post/:unknown"),
+        ];
+
+        for (url, baseurl, permalink, expected) in cases {
+            let site = SiteConfig {
+                url: url.to_string(),
+                baseurl: baseurl.to_string(),
+                permalink: permalink.to_string(),
+            };
+            assert_eq!(site.post_url(date, "test-post", &categories), expected);
+        }
+        assert_eq!(
+            SiteConfig::default().post_url(date, "test-post", &spaced),
+            "/city%20trip/2023/10/01/test-post.html"
+        );
+    }
+
+    #[test]
+    fn test_post_url_without_categories() {
+        let date = PostDate::parse("2023-10-01", Tz::UTC).unwrap();
+
+        assert_eq!(
+            SiteConfig::default().post_url(date, "test-post", &[]),
+            "/2023/10/01/test-post.html"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use deunicode::deunicode_char;
+
+const DEFAULT_REPLACEMENTS: [(&str, &str); 3] = [("æ", "ae"), ("ø", "o"), ("å", "a")];
+const DEFAULT_MAX_LENGTH: usize = 80;
+
+/// Turns titles into ASCII slugs for post file names and URLs.
+#[derive(Debug, PartialEq)]
+pub struct Slugger {
+    /// Applied before the general Unicode folding, like `ø` to `oe` instead of `o`.
+    replacements: Vec<(String, String)>,
+    max_length: usize,
+}
+
+impl Default for Slugger {
+    fn default() -> Self {
+        Slugger {
+            replacements: DEFAULT_REPLACEMENTS
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            max_length: DEFAULT_MAX_LENGTH,
+        }
+    }
+}
+
+impl Slugger {
+    /// Parses replacements written like `æ=ae, ø=oe, å=aa`.
+    pub fn new(replacements: &str, max_length: usize) -> Result<Self, String> {
+        check_max_length(max_length)?;
+        let replacements = replacements
+            .split(',')
+            .filter(|replacement| !replacement.trim().is_empty())
+            .map(|replacement| {
+                let (from, to) = replacement.split_once('=').ok_or_else(|| {
+                    format!("Invalid slug replacement {replacement}, use from=to")
+                })?;
+                Ok((from.trim().to_lowercase(), to.trim().to_lowercase()))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Slugger {
+            replacements,
+            max_length,
+        })
+    }
+
+    // SLUG_REPLACEMENTS replaces the default æ=ae, ø=o, å=a, SLUG_MAX_LENGTH defaults to 80
+    pub fn from_env() -> Result<Self, String> {
+        let default = Slugger::default();
+        let max_length = match std::env::var("SLUG_MAX_LENGTH") {
+            Ok(length) => length
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid SLUG_MAX_LENGTH: {length}"))?,
+            Err(_) => default.max_length,
+        };
+        check_max_length(max_length)?;
+        match std::env::var("SLUG_REPLACEMENTS") {
+            Ok(replacements) => Slugger::new(&replacements, max_length),
+            Err(_) => Ok(Slugger {
+                max_length,
+                ..default
+            }),
+        }
+    }
+
+    pub fn slugify(&self, text: &str) -> String {
+        let mut text = text.to_lowercase();
+        for (from, to) in &self.replacements {
+            text = text.replace(from.as_str(), to);
+        }
+
+        let folded: String = text
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii() => c.to_string(),
+                c => deunicode_char(c).unwrap_or(" ").to_lowercase(),
+            })
+            .collect::<String>()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), " ");
+
+        cut(&trim_whitespace(&folded), self.max_length).to_string()
+    }
+}
+
+fn check_max_length(max_length: usize) -> Result<(), String> {
+    if max_length == 0 {
+        return Err("Slug max length must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+// Cuts at the last word boundary that fits, or inside the first word when it is too long
+fn cut(slug: &str, max_length: usize) -> &str {
+    if slug.len() <= max_length {
+        return slug;
+    }
+    match slug[..=max_length].rfind('-') {
+        Some(end) if end > 0 => &slug[..end],
+        _ => &slug[..max_length],
+    }
+}
+
+// From https://stackoverflow.com/a/71864249/502493
+fn trim_whitespace(s: &str) -> String {
+    // second attempt: only allocate a string
+    let mut result = String::with_capacity(s.len());
+    s.split_whitespace().for_each(|w| {
+        if !result.is_empty() {
+            result.push('-');
+        }
+        result.push_str(w);
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_slugify() {
+        let cases = vec![
+            ("Hello World", "hello-world"),
+            ("Rust Programming!", "rust-programming"),
+            ("Multiple   Spaces", "multiple-spaces"),
+            ("Special@#Characters", "special-characters"),
+            ("--Already-Safe--", "already-safe"),
+            ("Trailing--", "trailing"),
+            (
+                "Day two, 108km, 864m - Wonderful weather on straight roads",
+                "day-two-108km-864m-wonderful-weather-on-straight-roads",
+            ),
+            ("Tur til Bødø", "tur-til-bodo"),
+            ("Blåbær og Ærfugl", "blabaer-og-aerfugl"),
+            ("Crème brûlée in Straße", "creme-brulee-in-strasse"),
+            ("Łódź → Kraków", "lodz-krakow"),
+            ("Søndag 🚲", "sondag-bike"),
+        ];
+
+        let slugger = Slugger::default();
+        for (input, expected) in cases {
+            assert_eq!(slugger.slugify(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_slugify_with_replacements() {
+        let slugger = Slugger::new("ø=oe, Å=aa", 80).unwrap();
+
+        assert_eq!(
+            slugger.slugify("Tur til Bødø på Åsen"),
+            "tur-til-boedoe-paa-aasen"
+        );
+        assert_eq!(
+            Slugger::new("ø", 80),
+            Err("Invalid slug replacement ø, use from=to".to_string())
+        );
+    }
+
+    #[test]
+    fn test_slugify_cuts_at_word_boundaries() {
+        let slugger = Slugger::new("", 20).unwrap();
+        assert_eq!(
+            Slugger::new("", 0),
+            Err("Slug max length must be at least 1".to_string())
+        );
+
+        assert_eq!(
+            slugger.slugify("Day two, 108km, 864m - Wonderful weather"),
+            "day-two-108km-864m"
+        );
+        assert_eq!(
+            slugger.slugify("Supercalifragilisticexpialidocious"),
+            "supercalifragilistic"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Activity details written to the front matter, in Strava's units (meters and seconds).
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Activity {
+    pub distance: f64,
+    pub moving_time: u64,
+    #[serde(alias = "total_elevation_gain")]
+    pub elevation_gain: f64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub polyline: String,
+}
+
+#[derive(Deserialize)]
+struct ActivityMap {
+    summary_polyline: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DetailedActivity {
+    #[serde(flatten)]
+    activity: Activity,
+    map: Option<ActivityMap>,
+}
+
+#[async_trait]
+pub trait StravaClient: Send + Sync {
+    async fn activity(&self, id: u64) -> Result<Activity, String>;
+}
+
+/// An app's refresh token, exchanged for access tokens that last six hours.
+pub struct Credentials {
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, Clone)]
+struct AccessToken {
+    access_token: String,
+    expires_at: i64,
+    refresh_token: String,
+}
+
+// Access tokens by client, reused until they are about to expire
+static TOKENS: Mutex<Option<HashMap<String, AccessToken>>> = Mutex::new(None);
+
+pub struct StravaApi {
+    client: reqwest::Client,
+    api_url: String,
+    credentials: Credentials,
+}
+
+impl StravaApi {
+    pub fn new(api_url: String, credentials: Credentials) -> Self {
+        StravaApi {
+            client: reqwest::Client::new(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            credentials,
+        }
+    }
+
+    async fn access_token(&self) -> Result<String, String> {
+        let key = format!("{} {}", self.api_url, self.credentials.client_id);
+        let cached = TOKENS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|tokens| tokens.get(&key).cloned());
+        // Refresh a minute early, so the token doesn't expire during the request
+        if let Some(token) = &cached
+            && token.expires_at - 60 > Utc::now().timestamp()
+        {
+            return Ok(token.access_token.clone());
+        }
+
+        // Strava may hand out a new refresh token, which replaces the configured one
+        let refresh_token = cached.map_or(self.credentials.refresh_token.clone(), |token| {
+            token.refresh_token
+        });
+        let response = self
+            .client
+            .post(format!("{}/oauth/token", self.api_url))
+            .form(&[
+                ("client_id", self.credentials.client_id.as_str()),
+                ("client_secret", self.credentials.client_secret.as_str()),
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to refresh access token: {status}: {body}"));
+        }
+        let token: AccessToken = response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse access token: {err}"))?;
+
+        info!("Refreshed Strava access token");
+        TOKENS
+            .lock()
+            .unwrap()
+            .get_or_insert_default()
+            .insert(key, token.clone());
+        Ok(token.access_token)
+    }
+}
+
+#[async_trait]
+impl StravaClient for StravaApi {
+    async fn activity(&self, id: u64) -> Result<Activity, String> {
+        let response = self
+            .client
+            .get(format!("{}/activities/{id}", self.api_url))
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await
+            .map_err(|err| format!("Request failed: {err}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to get activity {id}: {status}: {body}"));
+        }
+
+        let detailed: DetailedActivity = response
+            .json()
+            .await
+            .map_err(|err| format!("Failed to parse activity {id}: {err}"))?;
+
+        info!("Fetched Strava activity {id}");
+        Ok(Activity {
+            polyline: detailed
+                .map
+                .and_then(|map| map.summary_polyline)
+                .unwrap_or_default(),
+            ..detailed.activity
+        })
+    }
+}
+
+// STRAVA_CLIENT_ID, STRAVA_CLIENT_SECRET and STRAVA_REFRESH_TOKEN enable fetching activity details,
+// STRAVA_API_URL points it somewhere else
+pub fn from_env() -> Option<Box<dyn StravaClient>> {
+    let credentials = Credentials {
+        client_id: std::env::var("STRAVA_CLIENT_ID").ok()?,
+        client_secret: std::env::var("STRAVA_CLIENT_SECRET").ok()?,
+        refresh_token: std::env::var("STRAVA_REFRESH_TOKEN").ok()?,
+    };
+    let api_url = std::env::var("STRAVA_API_URL")
+        .unwrap_or_else(|_| "https://www.strava.com/api/v3".to_string());
+    Some(Box::new(StravaApi::new(api_url, credentials)))
+}
+
+/// Accepts an activity ID or a link to it, like https://www.strava.com/activities/123.
+pub fn parse_activity_id(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let id = match value.split_once("/activities/") {
+        Some((_, rest)) => rest.split(['/', '?', '#']).next().unwrap_or_default(),
+        None => value,
+    };
+
+    id.parse()
+        .map_err(|_| format!("Not a Strava activity: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use axum::extract::{Form, Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_parse_activity_id() {
+        let cases = vec![
+            ("123456789", Ok(123456789)),
+            (
+                " https://www.strava.com/activities/123456789 ",
+                Ok(123456789),
+            ),
+            (
+                "https://www.strava.com/activities/123456789/overview",
+                Ok(123456789),
+            ),
+            (
+                "strava.com/activities/123456789?share=1#comments",
+                Ok(123456789),
+            ),
+            (
+                "https://www.strava.com/athletes/42",
+                Err("Not a Strava activity: https://www.strava.com/athletes/42".to_string()),
+            ),
+            ("12ab", Err("Not a Strava activity: 12ab".to_string())),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_activity_id(input), expected);
+        }
+    }
+
+    type Refreshes = Arc<Mutex<Vec<String>>>;
+
+    // Hands out access tokens that expire after `expires_in` seconds, and records the refresh tokens used
+    async fn mock_strava(expires_in: i64) -> (String, Refreshes) {
+        let refreshes = Refreshes::default();
+        let app = Router::new()
+            .route(
+                "/oauth/token",
+                post(
+                    move |State(refreshes): State<Refreshes>,
+                          Form(form): Form<HashMap<String, String>>| async move {
+                        if form.get("client_secret").map(String::as_str) != Some("app-secret")
+                            || form.get("grant_type").map(String::as_str) != Some("refresh_token")
+                        {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        refreshes
+                            .lock()
+                            .unwrap()
+                            .push(form["refresh_token"].clone());
+                        Ok(Json(json!({
+                            "token_type": "Bearer",
+                            "access_token": "secret",
+                            "expires_at": Utc::now().timestamp() + expires_in,
+                            "refresh_token": "rotated",
+                        })))
+                    },
+                ),
+            )
+            .route(
+                "/activities/{id}",
+                get(|Path(id): Path<u64>, headers: HeaderMap| async move {
+                    if headers.get("authorization").and_then(|v| v.to_str().ok())
+                        != Some("Bearer secret")
+                    {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    if id != 42 {
+                        return Err(StatusCode::NOT_FOUND);
+                    }
+                    Ok(Json(json!({
+                        "id": 42,
+                        "name": "Morning Ride",
+                        "distance": 108231.5,
+                        "moving_time": 16200,
+                        "total_elevation_gain": 864.0,
+                        "map": { "id": "a42", "summary_polyline": "_p~iF~ps|U_ulLnnqC" },
+                    })))
+                }),
+            )
+            .with_state(refreshes.clone());
+
+        let url = test_server::serve(app).await;
+        (url, refreshes)
+    }
+
+    fn credentials(client_secret: &str) -> Credentials {
+        Credentials {
+            client_id: "1234".to_string(),
+            client_secret: client_secret.to_string(),
+            refresh_token: "refresh".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_activity() {
+        let (url, refreshes) = mock_strava(6 * 60 * 60).await;
+        let client = StravaApi::new(url.clone(), credentials("app-secret"));
+
+        assert_eq!(
+            client.activity(42).await,
+            Ok(Activity {
+                distance: 108231.5,
+                moving_time: 16200,
+                elevation_gain: 864.0,
+                polyline: "_p~iF~ps|U_ulLnnqC".to_string(),
+            })
+        );
+        // A new client, like the next request to a warm function, reuses the access token
+        let client = StravaApi::new(url, credentials("app-secret"));
+        assert!(client.activity(42).await.is_ok());
+        assert_eq!(*refreshes.lock().unwrap(), vec!["refresh"]);
+    }
+
+    #[tokio::test]
+    async fn test_activity_refreshes_expired_token() {
+        let (url, refreshes) = mock_strava(30).await;
+        let client = StravaApi::new(url, credentials("app-secret"));
+
+        assert!(client.activity(42).await.is_ok());
+        assert!(client.activity(42).await.is_ok());
+        assert_eq!(*refreshes.lock().unwrap(), vec!["refresh", "rotated"]);
+    }
+
+    #[tokio::test]
+    async fn test_activity_not_found() {
+        let (url, _) = mock_strava(6 * 60 * 60).await;
+        let client = StravaApi::new(url, credentials("app-secret"));
+
+        let err = client.activity(7).await.unwrap_err();
+        assert!(err.starts_with("Failed to get activity 7: 404"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_activity_with_invalid_credentials() {
+        let (url, _) = mock_strava(6 * 60 * 60).await;
+        let client = StravaApi::new(url, credentials("wrong"));
+
+        assert_eq!(
+            client.activity(42).await,
+            Err("Failed to refresh access token: 401 Unauthorized: ".to_string())
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::posts::ExistingPost;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Term {
+    pub name: String,
+    pub count: usize,
+}
+
+/// Every category and tag used in the repository, most used first.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Taxonomy {
+    pub categories: Vec<Term>,
+    pub tags: Vec<Term>,
+}
+
+// Warm function instances keep their statics, so this saves a repository scan per invocation.
+// The geocoding, Strava token and post caches rely on the same.
+static CACHE: Mutex<Option<(Instant, Taxonomy)>> = Mutex::new(None);
+
+/// Splits a comma separated list of categories or tags into trimmed, lowercase, unique terms.
+pub fn parse_terms(value: &str) -> Vec<String> {
+    normalize(value.split(',').map(String::from).collect())
+}
+
+pub fn normalize(terms: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = vec![];
+    for term in terms {
+        let term = term.trim().to_lowercase();
+        if !term.is_empty() && !normalized.contains(&term) {
+            normalized.push(term);
+        }
+    }
+    normalized
+}
+
+/// Warns about new terms that look like a typo of a term already used in the repository.
+pub fn near_duplicates(kind: &str, terms: &[String], existing: &BTreeSet<String>) -> Vec<String> {
+    terms
+        .iter()
+        .filter(|term| !existing.contains(*term))
+        .filter_map(|term| {
+            let similar = existing.iter().find(|known| is_similar(term, known))?;
+            Some(format!(
+                "{kind} \"{term}\" is new, did you mean \"{similar}\"?"
+            ))
+        })
+        .collect()
+}
+
+pub fn existing_categories(posts: &[ExistingPost]) -> BTreeSet<String> {
+    posts
+        .iter()
+        .flat_map(|post| post.front_matter.categories.iter().cloned())
+        .collect()
+}
+
+pub fn existing_tags(posts: &[ExistingPost]) -> BTreeSet<String> {
+    posts
+        .iter()
+        .flat_map(|post| post.front_matter.tags.iter().cloned())
+        .collect()
+}
+
+pub fn count(posts: &[ExistingPost]) -> Taxonomy {
+    fn terms<'a>(names: impl Iterator<Item = &'a String>) -> Vec<Term> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for name in names {
+            *counts.entry(name).or_default() += 1;
+        }
+
+        let mut terms: Vec<Term> = counts
+            .into_iter()
+            .map(|(name, count)| Term {
+                name: name.to_string(),
+                count,
+            })
+            .collect();
+        terms.sort_by_key(|term| std::cmp::Reverse(term.count));
+        terms
+    }
+
+    Taxonomy {
+        categories: terms(posts.iter().flat_map(|post| &post.front_matter.categories)),
+        tags: terms(posts.iter().flat_map(|post| &post.front_matter.tags)),
+    }
+}
+
+pub fn cached(max_age: Duration) -> Option<Taxonomy> {
+    match &*CACHE.lock().unwrap() {
+        Some((created, taxonomy)) if created.elapsed() < max_age => Some(taxonomy.clone()),
+        _ => None,
+    }
+}
+
+pub fn cache(taxonomy: &Taxonomy) {
+    *CACHE.lock().unwrap() = Some((Instant::now(), taxonomy.clone()));
+}
+
+pub fn invalidate_cache() {
+    *CACHE.lock().unwrap() = None;
+}
+
+// Same letters with different separators ("bike-packing"), or a small typo in a longer word
+fn is_similar(a: &str, b: &str) -> bool {
+    let letters = |s: &str| -> String { s.chars().filter(|c| c.is_alphanumeric()).collect() };
+    if letters(a) == letters(b) {
+        return true;
+    }
+
+    let shortest = a.chars().count().min(b.chars().count());
+    let allowed = match shortest {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    };
+    edit_distance(a, b) <= allowed
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::posts::PostFrontMatter;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_parse_terms() {
+        let cases = vec![
+            ("bikepacking, Norway", vec!["bikepacking", "norway"]),
+            (" Hiking ,hiking, HIKING ", vec!["hiking"]),
+            ("city trip,, ,summer", vec!["city trip", "summer"]),
+            ("", vec![]),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_terms(input), expected);
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let post = |categories: &str, tags: &str| ExistingPost {
+            path: String::new(),
+            front_matter: PostFrontMatter {
+                categories: parse_terms(categories),
+                tags: parse_terms(tags),
+                ..Default::default()
+            },
+        };
+        let posts = vec![
+            post("hiking, norway", "summit"),
+            post("bikepacking, norway", ""),
+            post("bikepacking, norway", "rain, summit"),
+        ];
+
+        let term = |name: &str, count| Term {
+            name: name.to_string(),
+            count,
+        };
+        assert_eq!(
+            count(&posts),
+            Taxonomy {
+                categories: vec![term("norway", 3), term("bikepacking", 2), term("hiking", 1)],
+                tags: vec![term("summit", 2), term("rain", 1)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let taxonomy = count(&[]);
+
+        cache(&taxonomy);
+        assert_eq!(cached(Duration::from_secs(60)), Some(taxonomy));
+        assert_eq!(cached(Duration::ZERO), None);
+
+        invalidate_cache();
+        assert_eq!(cached(Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn test_near_duplicates() {
+        let existing = BTreeSet::from([
+            "bikepacking".to_string(),
+            "hike".to_string(),
+            "norway".to_string(),
+        ]);
+        let terms = parse_terms("bike-packing, bikepaking, bike, norway, sweden");
+
+        assert_eq!(
+            near_duplicates("Category", &terms, &existing),
+            vec![
+                "Category \"bike-packing\" is new, did you mean \"bikepacking\"?",
+                "Category \"bikepaking\" is new, did you mean \"bikepacking\"?",
+            ]
+        );
+    }
+}
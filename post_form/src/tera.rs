@@ -1,8 +1,12 @@
-use log::{error, info};
+use crate::categories::Categories;
+use crate::dates::PostDate;
+use crate::geo::{self, MapProvider};
+use crate::strava::Activity;
+use crate::{markdown, trips};
+use chrono::NaiveDate;
+use log::error;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
 use tera::Tera;
 
 #[derive(Clone, Default, Serialize, Deserialize, Debug)]
@@ -14,123 +18,303 @@ pub struct ImageMetadata {
     pub alt_text: String,
     pub caption: String,
     pub image_url: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
 }
 
 #[derive(Deserialize, Serialize, Default)]
 pub struct UploadForm {
     pub title: String,
-    pub categories: String,
+    /// The post's file name and URL slug, generated from the title unless the form sets it.
+    pub slug: String,
+    pub categories: Vec<String>,
+    pub tags: Vec<String>,
     pub strava: String,
-    pub date: String,
+    pub strava_activity: Option<Activity>,
+    /// Publish even if another post already links to the same Strava activity.
+    pub allow_duplicate: bool,
+    pub date: Option<PostDate>,
+    /// Last day of a post that covers several days.
+    pub end_date: Option<NaiveDate>,
+    /// Identifier of the multi-day trip this post is part of, see `_data/trips/`.
+    pub trip: String,
+    pub day: Option<u32>,
+    /// Markdown before the first image, also used as the newsletter description.
+    pub intro: String,
+    /// Markdown after the last image.
+    pub outro: String,
     pub feature: ImageMetadata,
     pub images: HashMap<String, ImageMetadata>,
+    /// Extra front matter declared by the post's categories in `_plogtion/categories.yml`.
+    pub fields: BTreeMap<String, serde_yaml::Value>,
+    /// Custom front matter from `meta_` fields, see `meta::AllowList`.
+    pub meta: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl UploadForm {
-    pub fn validate(&self) -> Result<(), String> {
+    pub fn validate(&self, categories: &Categories) -> Result<(), String> {
         if self.title.is_empty() {
             return Err("Title cannot be empty".to_string());
         }
         if self.categories.is_empty() {
             return Err("Categories cannot be empty".to_string());
         }
-        if self.date.is_empty() {
+        let Some(date) = self.date else {
             return Err("Date cannot be empty".to_string());
+        };
+        if self.end_date.is_some_and(|end_date| end_date < date.day()) {
+            return Err("End date cannot be before the date".to_string());
         }
         if self.feature.image_url.is_empty() {
             return Err("Missing featured image".to_string());
         }
+        if !self.trip.is_empty() && !trips::is_valid_trip(&self.trip) {
+            return Err(format!(
+                "Invalid trip {}, use lowercase letters, digits and dashes",
+                self.trip
+            ));
+        }
+        if self.day.is_some() && self.trip.is_empty() {
+            return Err("Day requires a trip".to_string());
+        }
+        if self.day == Some(0) {
+            return Err("Day must start at 1".to_string());
+        }
+
+        markdown::validate_description(&self.intro)
+            .map_err(|err| format!("Invalid intro: {err}"))?;
+        markdown::validate_description(&self.outro)
+            .map_err(|err| format!("Invalid outro: {err}"))?;
+        let mut keys: Vec<&String> = self.images.keys().collect();
+        keys.sort();
+        for key in keys {
+            markdown::validate_description(&self.images[key].description)
+                .map_err(|err| format!("Invalid description for {key}: {err}"))?;
+        }
+
+        let schemas = categories.for_post(&self.categories);
+        for (category, schema) in &schemas {
+            if let Some(field) = schema
+                .required
+                .iter()
+                .find(|field| !self.fields.contains_key(*field))
+            {
+                return Err(format!("Category {category} requires {field}"));
+            }
+        }
+        if let Some(field) = self
+            .fields
+            .keys()
+            .find(|field| !schemas.iter().any(|(_, schema)| schema.allows(field)))
+        {
+            return Err(format!("Unexpected field: {field}"));
+        }
+        if let Some(field) = self.meta.keys().find(|key| self.fields.contains_key(*key)) {
+            return Err(format!(
+                "Field {field} is set both as a category field and as meta_{field}"
+            ));
+        }
+
         Ok(())
     }
 }
 
-pub fn create_post(upload_form: &UploadForm) -> Result<String, String> {
-    let (file_name_safe_title, rendered) = render(upload_form)?;
+#[derive(Serialize)]
+struct Feature<'a> {
+    image: &'a str,
+}
 
-    let file_name = format!(
-        "plog/_posts/{}-{}.md",
-        upload_form.date, file_name_safe_title
-    );
+/// The keys of the generated front matter, which form fields cannot replace.
+pub const FRONT_MATTER_KEYS: [&str; 11] = [
+    "title",
+    "date",
+    "end_date",
+    "categories",
+    "tags",
+    "feature",
+    "strava",
+    "strava_activity",
+    "trip",
+    "day",
+    "geojson",
+];
 
-    File::create(&file_name)
-        .and_then(|mut file| file.write_all(rendered.trim_end().as_bytes()))
-        .map_err(|err| {
-            error!("Failed to write rendered content to file: {err}");
-            "File writing failed".to_string()
-        })?;
+/// The post's front matter, serialized as YAML so that user input can't break out of it.
+#[derive(Serialize)]
+struct FrontMatter<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<PostDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_date: Option<NaiveDate>,
+    categories: &'a [String],
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    tags: &'a [String],
+    feature: Feature<'a>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    strava: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strava_activity: Option<&'a Activity>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    trip: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    day: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    geojson: Option<String>,
+    #[serde(flatten)]
+    fields: &'a BTreeMap<String, serde_yaml::Value>,
+    #[serde(flatten)]
+    meta: &'a BTreeMap<String, serde_yaml::Value>,
+}
 
-    info!("Post created successfully: {file_name}");
-    Ok(file_name_safe_title)
+impl<'a> FrontMatter<'a> {
+    fn new(form: &'a UploadForm) -> Self {
+        FrontMatter {
+            title: &form.title,
+            date: form.date,
+            end_date: form.end_date,
+            categories: &form.categories,
+            tags: &form.tags,
+            feature: Feature {
+                image: &form.feature.image_url,
+            },
+            strava: &form.strava,
+            strava_activity: form.strava_activity.as_ref(),
+            trip: &form.trip,
+            day: form.day,
+            geojson: form
+                .date
+                .filter(|_| geo::has_points(&form.images))
+                .map(|date| format!("/{}", geo::geojson_path(date.day(), &form.slug))),
+            fields: &form.fields,
+            meta: &form.meta,
+        }
+    }
 }
 
-fn render(upload_form: &UploadForm) -> Result<(String, String), String> {
-    let mut tera = Tera::default();
-    tera.add_raw_template("post.md", r##"---
-title: "{{ form.title }}"
-date: "{{ form.date }}"
-categories: "{{ form.categories }}"
-feature:
-  image: "{{ form.feature.image_url }}"
-{% if form.strava %}strava: "{{ form.strava }}"{% endif %}
----
+/// Renders the post's front matter and body, for the file named by the form's slug.
+pub fn render_post(
+    upload_form: &UploadForm,
+    template: Option<&str>,
+    map_provider: MapProvider,
+) -> Result<String, String> {
+    let rendered = render(upload_form, template, map_provider)?;
+    Ok(rendered.trim_end().to_string())
+}
+
+/// Repository path of a template that overrides the built-in one.
+/// Templates only render the body, the front matter is generated from the form.
+pub const TEMPLATE_PATH: &str = "_plogtion/post.md.tera";
 
+const DEFAULT_TEMPLATE: &str = r##"
+{%- if form.intro %}
+{{ form.intro }}{% endif %}
 {% for key, metadata in form.images %}
 ![{{ metadata.alt_text }}]({{ metadata.image_url }})
 {%- if metadata.caption %}
-*{%- if metadata.location %}[{{ metadata.location }}](https://www.google.com/maps/place/{{ metadata.coordinates }}): {% endif %}{{ metadata.caption }}*
+*{%- if metadata.location %}[{{ metadata.location | escape_markdown }}]({{ metadata.coordinates | map_url }}): {% endif %}{{ metadata.caption | escape_markdown }}*
 {% endif %}
 {%- if metadata.description %}
 {{ metadata.description }}
 {% endif %}
 {% endfor -%}
-"##).map_err(|err| {
-        error!("Failed to add template: {err}");
-        "Template initialization failed".to_string()
-    })?;
+{%- if form.outro %}
+{{ form.outro }}
+{% endif -%}
+"##;
+
+fn render(
+    upload_form: &UploadForm,
+    template: Option<&str>,
+    map_provider: MapProvider,
+) -> Result<String, String> {
+    let mut tera = Tera::default();
+    tera.register_filter(
+        "map_url",
+        move |value: &tera::Value, args: &HashMap<String, tera::Value>| {
+            map_url(value, args, map_provider)
+        },
+    );
+    tera.register_filter(
+        "escape_markdown",
+        |value: &tera::Value, _: &HashMap<String, tera::Value>| {
+            Ok(tera::Value::String(markdown::escape_inline(
+                value.as_str().unwrap_or_default(),
+            )))
+        },
+    );
+    tera.add_raw_template("post.md", template.unwrap_or(DEFAULT_TEMPLATE))
+        .map_err(|err| {
+            let err = describe_error(&err);
+            error!("Failed to add template: {err}");
+            format!("Invalid post template: {err}")
+        })?;
 
     let mut context = tera::Context::new();
     context.insert("form", upload_form);
 
-    let rendered = tera.render("post.md", &context).map_err(|err| {
+    let body = tera.render("post.md", &context).map_err(|err| {
+        let err = describe_error(&err);
         error!("Failed to render template: {err}");
-        "Template rendering failed".to_string()
+        format!("Template rendering failed: {err}")
     })?;
 
-    let file_name_safe_title = create_file_name_safe_title(&upload_form.title);
-    Ok((file_name_safe_title, rendered))
+    let front_matter = serde_yaml::to_string(&FrontMatter::new(upload_form)).map_err(|err| {
+        error!("Failed to serialize front matter: {err}");
+        format!("Front matter serialization failed: {err}")
+    })?;
+    Ok(format!("---\n{front_matter}---\n{body}"))
 }
 
-fn create_file_name_safe_title(title: &str) -> String {
-    trim_whitespace(&title.replace(|c: char| !c.is_alphanumeric(), " "))
-        .to_lowercase()
-        .to_string()
+/// `{{ metadata.coordinates | map_url }}` links to the coordinates on the configured map,
+/// `map_url(provider="openstreetmap")` on a specific one.
+fn map_url(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+    map_provider: MapProvider,
+) -> tera::Result<tera::Value> {
+    let provider = match args.get("provider").and_then(tera::Value::as_str) {
+        Some(name) => MapProvider::parse(name)?,
+        None => map_provider,
+    };
+    let coordinates = value.as_str().unwrap_or_default();
+    let (latitude, longitude) = geo::parse_coordinates(coordinates)
+        .ok_or_else(|| format!("map_url expects latitude,longitude, got {value}"))?;
+    Ok(tera::Value::String(provider.url(latitude, longitude)))
 }
 
-// From https://stackoverflow.com/a/71864249/502493
-pub fn trim_whitespace(s: &str) -> String {
-    // second attempt: only allocate a string
-    let mut result = String::with_capacity(s.len());
-    s.split_whitespace().for_each(|w| {
-        if !result.is_empty() {
-            result.push('-');
-        }
-        result.push_str(w);
-    });
-    result
+// Tera keeps the interesting part, like the line and column of a parse error, in the source chain
+fn describe_error(err: &tera::Error) -> String {
+    let mut description = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        description.push_str(&format!("\n{cause}"));
+        source = cause.source();
+    }
+    description
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::meta;
+    use chrono_tz::Tz;
     use pretty_assertions::assert_eq;
 
     #[test]
     fn test_render_post() {
         let upload_form = UploadForm {
             title: "Test Post".to_string(),
-            categories: "test, example".to_string(),
+            categories: vec!["test".to_string(), "example".to_string()],
+            tags: vec!["summer".to_string()],
             strava: "123456789".to_string(),
-            date: "2023-10-01".to_string(),
+            strava_activity: Some(Activity {
+                distance: 108231.5,
+                moving_time: 16200,
+                elevation_gain: 864.0,
+                polyline: "_p~iF~ps|U_ulLnnqC".to_string(),
+            }),
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
             feature: ImageMetadata {
                 image_url: "https://example.com/image.jpg".to_string(),
                 ..Default::default()
@@ -151,22 +335,31 @@ mod tests {
                     },
                 ),
             ]),
+            ..Default::default()
         };
 
-        let result = render(&upload_form);
+        let result = render(&upload_form, None, MapProvider::default());
         assert!(result.is_ok());
-        let (file_name_safe_title, rendered) = result.unwrap();
-        assert_eq!(file_name_safe_title, "test-post");
+        let rendered = result.unwrap();
         println!("{}", rendered);
         assert_eq!(
             rendered,
             r##"---
-title: "Test Post"
-date: "2023-10-01"
-categories: "test, example"
+title: Test Post
+date: 2023-10-01
+categories:
+- test
+- example
+tags:
+- summer
 feature:
-  image: "https://example.com/image.jpg"
-strava: "123456789"
+  image: https://example.com/image.jpg
+strava: '123456789'
+strava_activity:
+  distance: 108231.5
+  moving_time: 16200
+  elevation_gain: 864.0
+  polyline: _p~iF~ps|U_ulLnnqC
 ---
 
 
@@ -178,22 +371,358 @@ strava: "123456789"
     }
 
     #[test]
-    fn test_create_file_name_safe_title() {
-        let cases = vec![
-            ("Hello World", "hello-world"),
-            ("Rust Programming!", "rust-programming"),
-            ("Multiple   Spaces", "multiple-spaces"),
-            ("Special@#Characters", "special-characters"),
-            ("--Already-Safe--", "already-safe"),
-            ("Trailing--", "trailing"),
-            (
-                "Day two, 108km, 864m - Wonderful weather on straight roads",
-                "day-two-108km-864m-wonderful-weather-on-straight-roads",
+    fn test_render_post_with_intro_and_outro() {
+        let upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            intro: "We set off *early*.".to_string(),
+            outro: "Home again.\n\nMore soon.".to_string(),
+            images: HashMap::from([(
+                "key1".to_string(),
+                ImageMetadata {
+                    image_url: "https://example.com/image1.jpg".to_string(),
+                    caption: "Start".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        let (_, body) = rendered.rsplit_once("---\n").unwrap();
+        assert_eq!(
+            body,
+            "\nWe set off *early*.\n\n![](https://example.com/image1.jpg)\n*Start*\n\n\nHome again.\n\nMore soon.\n"
+        );
+    }
+
+    #[test]
+    fn test_render_post_with_time_and_end_date() {
+        let oslo: Tz = "Europe/Oslo".parse().unwrap();
+        let mut upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            categories: vec!["bikepacking".to_string()],
+            date: PostDate::parse("2023-10-01T14:30", oslo).ok(),
+            end_date: NaiveDate::from_ymd_opt(2023, 9, 30),
+            feature: ImageMetadata {
+                image_url: "https://example.com/image.jpg".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            upload_form.validate(&Categories::default()),
+            Err("End date cannot be before the date".to_string())
+        );
+
+        upload_form.end_date = NaiveDate::from_ymd_opt(2023, 10, 3);
+        assert_eq!(upload_form.validate(&Categories::default()), Ok(()));
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        assert!(
+            rendered.starts_with(
+                "---\ntitle: Test Post\ndate: 2023-10-01 14:30:00 +0200\nend_date: 2023-10-03\n"
+            ),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_render_post_with_category_fields() {
+        let upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            categories: vec!["bikepacking".to_string()],
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            fields: BTreeMap::from([
+                ("distance".to_string(), meta::parse_value("108")),
+                ("elevation".to_string(), meta::parse_value("864")),
+                ("surface".to_string(), meta::parse_value("gravel")),
+            ]),
+            ..Default::default()
+        };
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        assert!(
+            rendered.contains("  image: ''\ndistance: 108\nelevation: 864\nsurface: gravel\n---\n"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_render_post_with_meta_fields() {
+        let upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            categories: vec!["bikepacking".to_string()],
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            fields: BTreeMap::from([("distance".to_string(), meta::parse_value("108"))]),
+            meta: BTreeMap::from([
+                ("companions".to_string(), meta::parse_value("[Ola, Kari]")),
+                ("nights".to_string(), meta::parse_value("2")),
+                ("weather".to_string(), meta::parse_value("Rain: all day")),
+            ]),
+            ..Default::default()
+        };
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        assert!(
+            rendered.contains(
+                "distance: 108\ncompanions:\n- Ola\n- Kari\nnights: 2\nweather: 'Rain: all day'\n---\n"
             ),
-        ];
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_validate_category_fields() {
+        let categories =
+            Categories::parse("bikepacking:\n  required: [distance]\n  optional: [nights]\n")
+                .unwrap();
+        let mut upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            categories: vec!["bikepacking".to_string(), "norway".to_string()],
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            feature: ImageMetadata {
+                image_url: "https://example.com/image.jpg".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
 
-        for (input, expected) in cases {
-            assert_eq!(create_file_name_safe_title(input), expected);
+        assert_eq!(
+            upload_form.validate(&categories),
+            Err("Category bikepacking requires distance".to_string())
+        );
+
+        upload_form
+            .fields
+            .insert("distance".to_string(), meta::parse_value("108"));
+        upload_form
+            .fields
+            .insert("nights".to_string(), meta::parse_value("2"));
+        assert_eq!(upload_form.validate(&categories), Ok(()));
+
+        upload_form
+            .fields
+            .insert("summit".to_string(), meta::parse_value("Galdhøpiggen"));
+        assert_eq!(
+            upload_form.validate(&categories),
+            Err("Unexpected field: summit".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_descriptions() {
+        let mut upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            categories: vec!["hiking".to_string()],
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            feature: ImageMetadata {
+                image_url: "https://example.com/image.jpg".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for (key, description) in [("a.jpg", "Fine *words*"), ("b.jpg", "Oops\n\n---\n")] {
+            upload_form.images.insert(
+                key.to_string(),
+                ImageMetadata {
+                    description: description.to_string(),
+                    ..Default::default()
+                },
+            );
         }
+
+        assert_eq!(
+            upload_form.validate(&Categories::default()),
+            Err("Invalid description for b.jpg: Thematic break (---) on line 3".to_string())
+        );
+
+        upload_form.intro = "<iframe src=\"https://example.com\"></iframe>".to_string();
+        assert_eq!(
+            upload_form.validate(&Categories::default()),
+            Err("Invalid intro: Unsafe HTML element <iframe> on line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_trip() {
+        let mut upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            categories: vec!["bikepacking".to_string()],
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            feature: ImageMetadata {
+                image_url: "https://example.com/image.jpg".to_string(),
+                ..Default::default()
+            },
+            day: Some(2),
+            ..Default::default()
+        };
+        let categories = Categories::default();
+
+        assert_eq!(
+            upload_form.validate(&categories),
+            Err("Day requires a trip".to_string())
+        );
+
+        upload_form.trip = "Norway 2023".to_string();
+        assert_eq!(
+            upload_form.validate(&categories),
+            Err("Invalid trip Norway 2023, use lowercase letters, digits and dashes".to_string())
+        );
+
+        upload_form.trip = "norway-2023".to_string();
+        assert_eq!(upload_form.validate(&categories), Ok(()));
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        assert!(
+            rendered.contains("\ntrip: norway-2023\nday: 2\n---\n"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_render_post_references_geojson() {
+        let upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            slug: "test-post".to_string(),
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            images: HashMap::from([(
+                "key1".to_string(),
+                ImageMetadata {
+                    latitude: Some(60.3913),
+                    longitude: Some(5.3221),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        assert!(
+            rendered.contains("\ngeojson: /assets/geo/2023-10-01-test-post.geojson\n---\n"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_front_matter_keys_are_reserved() {
+        let upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            end_date: NaiveDate::from_ymd_opt(2023, 10, 3),
+            categories: vec!["bikepacking".to_string()],
+            tags: vec!["rain".to_string()],
+            strava: "https://www.strava.com/activities/1".to_string(),
+            strava_activity: Some(Activity::default()),
+            trip: "norway".to_string(),
+            day: Some(1),
+            images: HashMap::from([(
+                "key1".to_string(),
+                ImageMetadata {
+                    latitude: Some(60.3913),
+                    longitude: Some(5.3221),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let front_matter = serde_yaml::to_value(FrontMatter::new(&upload_form)).unwrap();
+        let keys: Vec<&str> = front_matter
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(|key| key.as_str())
+            .collect();
+
+        assert_eq!(keys, FRONT_MATTER_KEYS);
+        assert!(keys.iter().all(|key| meta::is_reserved(key)));
+    }
+
+    #[test]
+    fn test_render_post_links_locations_to_map_provider() {
+        let upload_form = UploadForm {
+            images: HashMap::from([(
+                "key1".to_string(),
+                ImageMetadata {
+                    location: "Bergen, Norway".to_string(),
+                    coordinates: "60.3913,5.3221".to_string(),
+                    caption: "Rain *all* day".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let rendered = render(&upload_form, None, MapProvider::OpenStreetMap).unwrap();
+        assert!(
+            rendered.contains("*[Bergen, Norway](https://www.openstreetmap.org/?mlat=60.3913&mlon=5.3221#map=15/60.3913/5.3221): Rain \\*all\\* day*"),
+            "{rendered}"
+        );
+
+        let template = "{{ form.images.key1.coordinates | map_url(provider=\"google\") }}";
+        let rendered = render(&upload_form, Some(template), MapProvider::OpenStreetMap).unwrap();
+        assert!(
+            rendered.ends_with("\nhttps://www.google.com/maps/place/60.3913,5.3221"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn test_render_post_with_repository_template() {
+        let upload_form = UploadForm {
+            title: "Test Post".to_string(),
+            ..Default::default()
+        };
+
+        let rendered = render(
+            &upload_form,
+            Some("# {{ form.title }}"),
+            MapProvider::default(),
+        )
+        .unwrap();
+        assert!(rendered.ends_with("\n---\n# Test Post"), "{rendered}");
+    }
+
+    #[test]
+    fn test_front_matter_round_trips_user_input() {
+        let upload_form = UploadForm {
+            title: "\"Quoted\" \\ back: slash\n---\ninjected: true".to_string(),
+            categories: vec!["a: b".to_string(), "'c'".to_string()],
+            strava: "#123".to_string(),
+            date: PostDate::parse("2023-10-01", Tz::UTC).ok(),
+            ..Default::default()
+        };
+
+        let rendered = render(&upload_form, None, MapProvider::default()).unwrap();
+        let front_matter = rendered
+            .strip_prefix("---\n")
+            .and_then(|rest| rest.split_once("\n---\n"))
+            .map(|(front_matter, _)| front_matter)
+            .unwrap();
+        let parsed: serde_yaml::Mapping = serde_yaml::from_str(front_matter).unwrap();
+
+        assert_eq!(parsed["title"].as_str(), Some(upload_form.title.as_str()));
+        assert_eq!(
+            parsed["categories"],
+            serde_yaml::to_value(&upload_form.categories).unwrap()
+        );
+        assert_eq!(parsed["strava"].as_str(), Some("#123"));
+        assert!(!parsed.contains_key("injected"));
+    }
+
+    #[test]
+    fn test_render_reports_template_errors_with_line_numbers() {
+        let template = "\n![]({{ form.feature.image_url }})\n\n{% for %}\n";
+
+        let err = render(
+            &UploadForm::default(),
+            Some(template),
+            MapProvider::default(),
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Invalid post template: "), "{err}");
+        assert!(err.contains("--> 4:8"), "{err}");
     }
 }
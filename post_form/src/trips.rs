@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+
+pub fn trip_path(trip: &str) -> String {
+    format!("_data/trips/{trip}.yml")
+}
+
+/// Trip identifiers end up in a file name, so keep them to lowercase letters, digits and dashes.
+pub fn is_valid_trip(trip: &str) -> bool {
+    !trip.is_empty()
+        && trip
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TripPost {
+    pub day: u32,
+    pub title: String,
+    pub date: String,
+    pub path: String,
+    pub url: String,
+}
+
+/// A `_data/trips/<trip>.yml` file, listing the trip's posts in order for prev/next navigation.
+/// Keys other than `posts`, like a hand written `title`, are kept as they are.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct Trip {
+    #[serde(flatten)]
+    pub other: serde_yaml::Mapping,
+    #[serde(default)]
+    pub posts: Vec<TripPost>,
+}
+
+impl Trip {
+    pub fn parse(yaml: Option<&str>) -> Result<Self, String> {
+        match yaml {
+            Some(yaml) => serde_yaml::from_str(yaml).map_err(|err| format!("Invalid trip: {err}")),
+            None => Ok(Trip::default()),
+        }
+    }
+
+    pub fn next_day(&self) -> u32 {
+        self.posts.iter().map(|post| post.day).max().unwrap_or(0) + 1
+    }
+
+    /// Adds or replaces the post with the same path, keeping the posts ordered by day.
+    pub fn add_post(&mut self, post: TripPost) {
+        self.posts.retain(|existing| existing.path != post.path);
+        self.posts.push(post);
+        self.posts
+            .sort_by(|a, b| (a.day, &a.date, &a.path).cmp(&(b.day, &b.date, &b.path)));
+    }
+
+    pub fn to_yaml(&self) -> Result<String, String> {
+        serde_yaml::to_string(self).map_err(|err| format!("Failed to serialize trip: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn post(day: u32, slug: &str) -> TripPost {
+        TripPost {
+            day,
+            title: slug.to_string(),
+            date: format!("2023-10-0{day}"),
+            path: format!("_posts/2023-10-0{day}-{slug}.md"),
+            url: format!("https://kyrremann.no/plog/2023/10/{slug}"),
+        }
+    }
+
+    #[test]
+    fn test_is_valid_trip() {
+        assert!(is_valid_trip("norway-2023"));
+        assert!(!is_valid_trip(""));
+        assert!(!is_valid_trip("Norway"));
+        assert!(!is_valid_trip("../norway"));
+    }
+
+    #[test]
+    fn test_add_post_keeps_posts_in_order() {
+        let mut trip = Trip::parse(None).unwrap();
+        assert_eq!(trip.next_day(), 1);
+
+        trip.add_post(post(2, "day-two"));
+        trip.add_post(post(1, "day-one"));
+        trip.add_post(post(3, "day-three"));
+        assert_eq!(trip.next_day(), 4);
+
+        let mut retitled = post(2, "day-two");
+        retitled.title = "Day two, again".to_string();
+        trip.add_post(retitled.clone());
+
+        assert_eq!(
+            trip.posts,
+            vec![post(1, "day-one"), retitled, post(3, "day-three")]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_keeps_other_keys() {
+        let yaml = "title: Norway by bike\nposts:\n- day: 1\n  title: day-one\n  date: 2023-10-01\n  path: _posts/2023-10-01-day-one.md\n  url: https://kyrremann.no/plog/2023/10/day-one\n";
+
+        let mut trip = Trip::parse(Some(yaml)).unwrap();
+        assert_eq!(trip.posts, vec![post(1, "day-one")]);
+
+        trip.add_post(post(2, "day-two"));
+        let yaml = trip.to_yaml().unwrap();
+        assert!(
+            yaml.starts_with("title: Norway by bike\nposts:\n- day: 1\n"),
+            "{yaml}"
+        );
+        assert_eq!(Trip::parse(Some(&yaml)).unwrap(), trip);
+    }
+}